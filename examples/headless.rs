@@ -0,0 +1,81 @@
+//! Renders a scene built in code to a PNG, without opening a window.
+//!
+//! ```sh
+//! cargo run --example headless -- spheres.png
+//! ```
+
+use std::error::Error;
+
+use nalgebra_glm::vec3;
+use vulkano::{device::DeviceExtensions, instance::InstanceExtensions, VulkanLibrary};
+use wreckage::{
+    Camera, Capsule, Light, Material, NaiveRenderer, Plane, ReferenceRenderer, Renderer,
+    RenderingContext, Scene, Sphere,
+};
+
+fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let output = std::env::args().nth(1).unwrap_or("spheres.png".into());
+    let size = [640, 480];
+
+    let scene = Scene {
+        camera: Camera {
+            position: vec3(0.0, -1.0, 0.0),
+            ..Default::default()
+        }
+        .looking_at(&vec3(0.0, 0.0, -6.0), &vec3(0.0, 1.0, 0.0)),
+        spheres: vec![
+            Sphere::new(vec3(-1.5, 0.0, -6.0), 1.0).with_material(1),
+            Sphere::new(vec3(1.5, 0.0, -6.0), 1.0).with_material(2),
+        ],
+        // Any other shape goes with the primitives
+        primitives: vec![
+            Plane::new(vec3(0.0, 1.0, 0.0), -1.0)
+                .with_material(0)
+                .into(),
+            Capsule::new(vec3(-0.5, -0.5, -4.5), vec3(0.5, -0.5, -4.5), 0.4).into(),
+        ],
+        // Meshes are loaded from OBJ files, see `MeshInstance`
+        meshes: vec![],
+        materials: vec![
+            Material::Diffuse {
+                albedo: vec3(0.4, 0.4, 0.4),
+            },
+            Material::Diffuse {
+                albedo: vec3(0.9, 0.2, 0.2),
+            },
+            Material::Metal {
+                albedo: vec3(0.8, 0.8, 0.8),
+                roughness: 0.2,
+            },
+        ],
+        lights: vec![Light::Point {
+            position: vec3(2.0, 3.0, -3.0),
+            colour: vec3(1.0, 0.95, 0.9),
+            intensity: 20.0,
+        }],
+    };
+
+    // Only a device is needed, no surface or swapchain extensions
+    let frame = match VulkanLibrary::new() {
+        Ok(library) => {
+            let ctx = RenderingContext::new(
+                library,
+                InstanceExtensions::empty(),
+                DeviceExtensions::empty(),
+            )?;
+            let mut renderer = NaiveRenderer::headless(ctx, size, &scene)?;
+
+            // The camera can be moved between captures
+            renderer.set_camera(&scene.camera);
+            renderer.capture()?
+        }
+        Err(e) => {
+            eprintln!("No Vulkan library ({e}), rendering on the CPU");
+            ReferenceRenderer::new(size, &scene).render(&scene.camera)
+        }
+    };
+
+    frame.save(&output)?;
+    println!("Wrote {output}");
+    Ok(())
+}
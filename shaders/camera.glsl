@@ -0,0 +1,62 @@
+// Ray generation shared by the pipelines, the Rust side is `Camera::raw`
+
+#define PROJECTION_PERSPECTIVE 0
+#define PROJECTION_ORTHOGRAPHIC 1
+#define PROJECTION_FISHEYE 2
+#define PROJECTION_EQUIRECTANGULAR 3
+
+// The ray through uv, which goes from (0, 0) to (1, 1) across the frame, in the camera's
+// space. Returns false where the projection doesn't cover the frame.
+//
+// Perspective and orthographic rays have a length of 1 along the view axis, so their hit
+// distances are depths. The others are normalised and measure distance from the camera.
+bool camera_ray(vec2 uv, float aspect_ratio, uint projection, float fov, float ortho_height, out Ray ray) {
+    vec2 ndc = uv * 2 - 1;
+    ray.origin = vec3(0);
+
+    switch (projection) {
+    case PROJECTION_ORTHOGRAPHIC:
+        ray.origin = vec3(ndc.x * aspect_ratio, ndc.y, 0) * ortho_height / 2;
+        ray.direction = vec3(0, 0, -1);
+        return true;
+    case PROJECTION_FISHEYE:
+        // Equidistant, the angle off the view axis grows evenly towards the edges
+        vec2 p = vec2(ndc.x * aspect_ratio, ndc.y);
+        float r = length(p);
+        float theta = r * fov / 2;
+        if (theta > PI) {
+            return false;
+        }
+        vec2 side = r > 0 ? p / r * sin(theta) : vec2(0);
+        ray.direction = vec3(side, -cos(theta));
+        return true;
+    case PROJECTION_EQUIRECTANGULAR:
+        // Longitude across the width, latitude up the height
+        float longitude = ndc.x * PI;
+        float latitude = ndc.y * PI / 2;
+        ray.direction = vec3(
+            sin(longitude) * cos(latitude),
+            sin(latitude),
+            -cos(longitude) * cos(latitude));
+        return true;
+    default:
+        float tan_half_fov = tan(fov / 2);
+        ray.direction = vec3(ndc.x * aspect_ratio * tan_half_fov, ndc.y * tan_half_fov, -1);
+        return true;
+    }
+}
+
+// Moves the ray's origin to a point on the lens picked by u, in [0, 1) squared, and aims
+// it at where the pinhole ray crosses the focus distance, which stays sharp
+void thin_lens(inout Ray ray, vec2 u, float aperture, float focus_distance) {
+    // Uniform over the disk of the lens
+    float r = aperture * sqrt(u.x);
+    float a = 2 * PI * u.y;
+    vec3 lens = ray.origin + vec3(r * cos(a), r * sin(a), 0);
+
+    vec3 focus = ray.origin + ray.direction * focus_distance;
+    // Perspective and orthographic rays keep their length along the view axis, so
+    // `near` and `far` still measure the same. Normalized rays only stay close to unit.
+    ray.direction = (focus - lens) / focus_distance;
+    ray.origin = lens;
+}
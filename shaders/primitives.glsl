@@ -0,0 +1,471 @@
+// Ray intersection with every kind of primitive, shared by the compute shaders.
+// Distances are measured in units of the ray direction, which isn't normalised.
+
+struct Ray {
+    vec3 origin;
+    vec3 direction;
+};
+
+#define PRIMITIVE_SPHERE 0
+#define PRIMITIVE_PLANE 1
+#define PRIMITIVE_BOX 2
+#define PRIMITIVE_ORIENTED_BOX 3
+#define PRIMITIVE_CYLINDER 4
+#define PRIMITIVE_CAPSULE 5
+#define PRIMITIVE_TORUS 6
+#define PRIMITIVE_MESH 7
+
+#define NO_MATERIAL 0xFFFFFFFFu
+
+// What a, b and c hold depends on the kind:
+//   sphere:       a = (center, radius)
+//   plane:        a = (unit normal, distance from the origin)
+//   box:          a = (min, _), b = (max, _)
+//   oriented box: a = (center, _), b = (half extents, _), c = rotation
+//   cylinder:     a = (start, radius), b = (end, _)
+//   capsule:      a = (start, radius), b = (end, _)
+//   torus:        a = (center, major radius), b = (_, minor radius), c = rotation
+//   mesh:         a = (position, scale), c = rotation, root = first node of its hierarchy
+// Rotations are quaternions, the torus lies in the XZ plane before it's turned.
+struct Primitive {
+    vec4 a;
+    vec4 b;
+    vec4 c;
+    uint kind;
+    // NO_MATERIAL means the primitive is shaded by its normal
+    uint material;
+    uint root;
+};
+
+// Interior nodes have a count of zero and their children at first and first + 1,
+// leaves cover the primitives, or the triangles of a mesh, first..first + count
+struct BvhNode {
+    vec3 min;
+    uint first;
+    vec3 max;
+    uint count;
+};
+
+struct Vertex {
+    vec3 position;
+    vec3 normal;
+};
+
+// The meshes of the scene one after another, at the same bindings in every pipeline
+layout(std430, binding = 6) readonly buffer Vertices {
+    Vertex vertices[];
+} vertices;
+
+// Three per triangle
+layout(std430, binding = 7) readonly buffer Indices {
+    uint indices[];
+} indices;
+
+layout(std430, binding = 8) readonly buffer MeshHierarchy {
+    BvhNode nodes[];
+} mesh_bvh;
+
+// Stand in for infinity where a primitive doesn't bound the ray
+#define FAR_AWAY 1e30
+#define TORUS_STEPS 128
+#define TORUS_EPSILON 1e-4
+#define MESH_STACK_SIZE 32
+
+vec3 rotate(vec4 q, vec3 v) {
+    return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
+}
+
+vec3 unrotate(vec4 q, vec3 v) {
+    return rotate(vec4(-q.xyz, q.w), v);
+}
+
+// Picks the entry of the interval if it's in range, else the exit
+bool pick_root(float enter, float exit, float t_min, float t_max, out float t, out bool entering) {
+    entering = t_min <= enter && enter <= t_max;
+    t = entering ? enter : exit;
+    return entering || (t_min <= exit && exit <= t_max);
+}
+
+// Where the ray is inside the sphere
+bool sphere_interval(Ray ray, vec3 center, float radius, out float enter, out float exit) {
+    if (radius <= 0) {
+        return false;
+    }
+
+    vec3 oc = ray.origin - center;
+    float a = dot(ray.direction, ray.direction);
+    float half_b = dot(oc, ray.direction);
+    float c = dot(oc, oc) - radius * radius;
+    float discriminant = half_b * half_b - c * a;
+    if (discriminant < 0) {
+        return false;
+    }
+
+    float sqrtd = sqrt(discriminant);
+    enter = (-half_b - sqrtd) / a;
+    exit = (-half_b + sqrtd) / a;
+    return true;
+}
+
+// Where the ray is inside the box, with the normals of the faces it crosses
+bool box_interval(Ray ray, vec3 lo, vec3 hi, out float enter, out float exit, out vec3 enter_normal, out vec3 exit_normal) {
+    vec3 inv_direction = 1.0 / ray.direction;
+    vec3 t0 = (lo - ray.origin) * inv_direction;
+    vec3 t1 = (hi - ray.origin) * inv_direction;
+    vec3 t_near = min(t0, t1);
+    vec3 t_far = max(t0, t1);
+
+    enter = max(max(t_near.x, t_near.y), t_near.z);
+    exit = min(min(t_far.x, t_far.y), t_far.z);
+    if (enter > exit) {
+        return false;
+    }
+
+    vec3 enter_axis = vec3(equal(t_near, vec3(enter)));
+    vec3 exit_axis = vec3(equal(t_far, vec3(exit)));
+    // Only keep the first axis when the ray crosses an edge
+    enter_axis = enter_axis.x > 0 ? vec3(1, 0, 0) : enter_axis.y > 0 ? vec3(0, 1, 0) : vec3(0, 0, 1);
+    exit_axis = exit_axis.x > 0 ? vec3(1, 0, 0) : exit_axis.y > 0 ? vec3(0, 1, 0) : vec3(0, 0, 1);
+    enter_normal = -sign(ray.direction) * enter_axis;
+    exit_normal = sign(ray.direction) * exit_axis;
+    return true;
+}
+
+// Where the ray is inside the infinite cylinder around start..end
+bool tube_interval(Ray ray, vec3 start, vec3 end, float radius, out float enter, out float exit) {
+    vec3 axis = end - start;
+    vec3 oc = ray.origin - start;
+    float axis_squared = dot(axis, axis);
+
+    // Parts of the origin and direction perpendicular to the axis
+    vec3 oc_perp = oc - axis * dot(oc, axis) / axis_squared;
+    vec3 dir_perp = ray.direction - axis * dot(ray.direction, axis) / axis_squared;
+
+    float a = dot(dir_perp, dir_perp);
+    float half_b = dot(oc_perp, dir_perp);
+    float c = dot(oc_perp, oc_perp) - radius * radius;
+    if (a < 1e-12) {
+        // Parallel to the axis, inside all along or never
+        enter = -FAR_AWAY;
+        exit = FAR_AWAY;
+        return c <= 0;
+    }
+
+    float discriminant = half_b * half_b - c * a;
+    if (discriminant < 0) {
+        return false;
+    }
+
+    float sqrtd = sqrt(discriminant);
+    enter = (-half_b - sqrtd) / a;
+    exit = (-half_b + sqrtd) / a;
+    return true;
+}
+
+// Where the ray is between the planes through start and end that are normal to the axis
+bool slab_interval(Ray ray, vec3 start, vec3 end, out float enter, out float exit) {
+    vec3 axis = end - start;
+    float along = dot(ray.direction, axis);
+    float offset = dot(ray.origin - start, axis);
+    float axis_squared = dot(axis, axis);
+
+    if (abs(along) < 1e-12) {
+        enter = -FAR_AWAY;
+        exit = FAR_AWAY;
+        return 0 <= offset && offset <= axis_squared;
+    }
+
+    float t0 = -offset / along;
+    float t1 = (axis_squared - offset) / along;
+    enter = min(t0, t1);
+    exit = max(t0, t1);
+    return enter <= exit;
+}
+
+bool intersect_sphere(Primitive p, Ray ray, float t_min, float t_max, out float t, out vec3 normal) {
+    float enter, exit;
+    bool entering;
+    if (!sphere_interval(ray, p.a.xyz, p.a.w, enter, exit) || !pick_root(enter, exit, t_min, t_max, t, entering)) {
+        return false;
+    }
+    normal = (ray.origin + ray.direction * t - p.a.xyz) / p.a.w;
+    return true;
+}
+
+bool intersect_plane(Primitive p, Ray ray, float t_min, float t_max, out float t, out vec3 normal) {
+    float denominator = dot(p.a.xyz, ray.direction);
+    if (denominator == 0) {
+        return false;
+    }
+    t = (p.a.w - dot(p.a.xyz, ray.origin)) / denominator;
+    normal = p.a.xyz;
+    return t_min <= t && t <= t_max;
+}
+
+bool intersect_box(Primitive p, Ray ray, float t_min, float t_max, out float t, out vec3 normal) {
+    float enter, exit;
+    vec3 enter_normal, exit_normal;
+    bool entering;
+    if (!box_interval(ray, p.a.xyz, p.b.xyz, enter, exit, enter_normal, exit_normal)
+            || !pick_root(enter, exit, t_min, t_max, t, entering)) {
+        return false;
+    }
+    normal = entering ? enter_normal : exit_normal;
+    return true;
+}
+
+bool intersect_oriented_box(Primitive p, Ray ray, float t_min, float t_max, out float t, out vec3 normal) {
+    // Rotations keep lengths, so distances along the local ray are the same
+    Ray local;
+    local.origin = unrotate(p.c, ray.origin - p.a.xyz);
+    local.direction = unrotate(p.c, ray.direction);
+
+    float enter, exit;
+    vec3 enter_normal, exit_normal;
+    bool entering;
+    if (!box_interval(local, -p.b.xyz, p.b.xyz, enter, exit, enter_normal, exit_normal)
+            || !pick_root(enter, exit, t_min, t_max, t, entering)) {
+        return false;
+    }
+    normal = rotate(p.c, entering ? enter_normal : exit_normal);
+    return true;
+}
+
+bool intersect_cylinder(Primitive p, Ray ray, float t_min, float t_max, out float t, out vec3 normal) {
+    vec3 start = p.a.xyz;
+    vec3 end = p.b.xyz;
+    float tube_enter, tube_exit, slab_enter, slab_exit;
+    if (!tube_interval(ray, start, end, p.a.w, tube_enter, tube_exit)
+            || !slab_interval(ray, start, end, slab_enter, slab_exit)) {
+        return false;
+    }
+
+    // Inside the cylinder where the ray is inside both the tube and the slab
+    float enter = max(tube_enter, slab_enter);
+    float exit = min(tube_exit, slab_exit);
+    bool entering;
+    if (enter > exit || !pick_root(enter, exit, t_min, t_max, t, entering)) {
+        return false;
+    }
+
+    vec3 axis = normalize(end - start);
+    bool on_cap = entering ? slab_enter > tube_enter : slab_exit < tube_exit;
+    if (on_cap) {
+        // The cap the ray crosses first faces against it
+        float along = sign(dot(ray.direction, axis));
+        normal = entering ? -along * axis : along * axis;
+    } else {
+        vec3 offset = ray.origin + ray.direction * t - start;
+        normal = normalize(offset - axis * dot(offset, axis));
+    }
+    return true;
+}
+
+bool intersect_capsule(Primitive p, Ray ray, float t_min, float t_max, out float t, out vec3 normal) {
+    vec3 start = p.a.xyz;
+    vec3 end = p.b.xyz;
+    float radius = p.a.w;
+
+    // The capsule is convex, so the ray is inside it from the first entry into
+    // any of its parts until the last exit
+    float enter = FAR_AWAY;
+    float exit = -FAR_AWAY;
+    float part_enter, part_exit, slab_enter, slab_exit;
+    if (sphere_interval(ray, start, radius, part_enter, part_exit)) {
+        enter = min(enter, part_enter);
+        exit = max(exit, part_exit);
+    }
+    if (sphere_interval(ray, end, radius, part_enter, part_exit)) {
+        enter = min(enter, part_enter);
+        exit = max(exit, part_exit);
+    }
+    if (tube_interval(ray, start, end, radius, part_enter, part_exit)
+            && slab_interval(ray, start, end, slab_enter, slab_exit)) {
+        part_enter = max(part_enter, slab_enter);
+        part_exit = min(part_exit, slab_exit);
+        if (part_enter <= part_exit) {
+            enter = min(enter, part_enter);
+            exit = max(exit, part_exit);
+        }
+    }
+
+    bool entering;
+    if (enter > exit || !pick_root(enter, exit, t_min, t_max, t, entering)) {
+        return false;
+    }
+
+    // The normal points away from the closest point on the segment
+    vec3 point = ray.origin + ray.direction * t;
+    vec3 axis = end - start;
+    float h = clamp(dot(point - start, axis) / max(dot(axis, axis), 1e-12), 0, 1);
+    normal = normalize(point - start - axis * h);
+    return true;
+}
+
+float torus_distance(vec3 point, float major_radius, float minor_radius) {
+    vec2 q = vec2(length(point.xz) - major_radius, point.y);
+    return length(q) - minor_radius;
+}
+
+// A torus needs a quartic solved, sphere tracing its distance field is simpler and stable
+bool intersect_torus(Primitive p, Ray ray, float t_min, float t_max, out float t, out vec3 normal) {
+    float major_radius = p.a.w;
+    float minor_radius = p.b.w;
+
+    Ray local;
+    local.origin = unrotate(p.c, ray.origin - p.a.xyz);
+    local.direction = unrotate(p.c, ray.direction);
+
+    // Only march where the ray is inside the bounding sphere
+    float enter, exit;
+    if (!sphere_interval(local, vec3(0), major_radius + minor_radius, enter, exit)) {
+        return false;
+    }
+
+    // March in world units along the normalised direction
+    float scale = length(local.direction);
+    vec3 dir = local.direction / scale;
+    float s = max(t_min, enter) * scale;
+    float end = min(t_max, exit) * scale;
+    if (s > end) {
+        return false;
+    }
+
+    // Rays starting inside the tube march towards where they leave it
+    float side = torus_distance(local.origin + dir * s, major_radius, minor_radius) < 0 ? -1 : 1;
+    for (int i = 0; i < TORUS_STEPS; i++) {
+        vec3 point = local.origin + dir * s;
+        float distance = side * torus_distance(point, major_radius, minor_radius);
+        if (distance < TORUS_EPSILON) {
+            vec2 ring = normalize(point.xz) * major_radius;
+            normal = rotate(p.c, normalize(point - vec3(ring.x, 0, ring.y)));
+            t = s / scale;
+            return true;
+        }
+        s += distance;
+        if (s > end) {
+            return false;
+        }
+    }
+
+    return false;
+}
+
+// Whether the ray passes through the box somewhere within t_min..t_max
+bool hits_bounds(Ray ray, vec3 inv_direction, vec3 lo, vec3 hi, float t_min, float t_max) {
+    vec3 t0 = (lo - ray.origin) * inv_direction;
+    vec3 t1 = (hi - ray.origin) * inv_direction;
+    vec3 t_near = min(t0, t1);
+    vec3 t_far = max(t0, t1);
+
+    float enter = max(max(t_near.x, t_near.y), max(t_near.z, t_min));
+    float exit = min(min(t_far.x, t_far.y), min(t_far.z, t_max));
+    return enter <= exit;
+}
+
+// Möller-Trumbore, uv are the barycentric coordinates of the hit along the edges from p0
+bool intersect_triangle(Ray ray, vec3 p0, vec3 p1, vec3 p2, float t_min, float t_max, out float t, out vec2 uv) {
+    vec3 edge1 = p1 - p0;
+    vec3 edge2 = p2 - p0;
+    vec3 p = cross(ray.direction, edge2);
+    float determinant = dot(edge1, p);
+    if (determinant == 0) {
+        return false;
+    }
+
+    float inv_determinant = 1.0 / determinant;
+    vec3 s = ray.origin - p0;
+    uv.x = dot(s, p) * inv_determinant;
+    if (uv.x < 0 || uv.x > 1) {
+        return false;
+    }
+
+    vec3 q = cross(s, edge1);
+    uv.y = dot(ray.direction, q) * inv_determinant;
+    if (uv.y < 0 || uv.x + uv.y > 1) {
+        return false;
+    }
+
+    t = dot(edge2, q) * inv_determinant;
+    return t_min <= t && t <= t_max;
+}
+
+bool intersect_mesh(Primitive p, Ray ray, float t_min, float t_max, out float t, out vec3 normal) {
+    // Scaling the direction along with the origin keeps distances along the ray the same
+    Ray local;
+    local.origin = unrotate(p.c, ray.origin - p.a.xyz) / p.a.w;
+    local.direction = unrotate(p.c, ray.direction) / p.a.w;
+    vec3 inv_direction = 1.0 / local.direction;
+
+    bool hit = false;
+    uint hit_triangle = 0;
+    vec2 hit_uv;
+    t = t_max;
+
+    uint stack[MESH_STACK_SIZE];
+    uint stack_size = 0;
+    stack[stack_size++] = p.root;
+
+    while (stack_size > 0) {
+        BvhNode node = mesh_bvh.nodes[stack[--stack_size]];
+        if (!hits_bounds(local, inv_direction, node.min, node.max, t_min, t))
+            continue;
+
+        if (node.count > 0) {
+            for (uint i = node.first; i < node.first + node.count; i++) {
+                vec3 p0 = vertices.vertices[indices.indices[i * 3]].position;
+                vec3 p1 = vertices.vertices[indices.indices[i * 3 + 1]].position;
+                vec3 p2 = vertices.vertices[indices.indices[i * 3 + 2]].position;
+
+                float triangle_t;
+                vec2 uv;
+                if (intersect_triangle(local, p0, p1, p2, t_min, t, triangle_t, uv)) {
+                    hit = true;
+                    t = triangle_t;
+                    hit_triangle = i;
+                    hit_uv = uv;
+                }
+            }
+        } else if (stack_size + 2 <= MESH_STACK_SIZE) {
+            stack[stack_size++] = node.first + 1;
+            stack[stack_size++] = node.first;
+        }
+    }
+
+    if (!hit) {
+        return false;
+    }
+
+    Vertex v0 = vertices.vertices[indices.indices[hit_triangle * 3]];
+    Vertex v1 = vertices.vertices[indices.indices[hit_triangle * 3 + 1]];
+    Vertex v2 = vertices.vertices[indices.indices[hit_triangle * 3 + 2]];
+    normal = (1 - hit_uv.x - hit_uv.y) * v0.normal + hit_uv.x * v1.normal + hit_uv.y * v2.normal;
+    // Fall back to the face when the vertex normals cancel out
+    if (dot(normal, normal) < 1e-12) {
+        normal = cross(v1.position - v0.position, v2.position - v0.position);
+    }
+    normal = rotate(p.c, normalize(normal));
+    return true;
+}
+
+// Finds where the ray hits the primitive within t_min..t_max, the normal faces out of it
+bool intersect_primitive(Primitive p, Ray ray, float t_min, float t_max, out float t, out vec3 normal) {
+    switch (p.kind) {
+    case PRIMITIVE_PLANE:
+        return intersect_plane(p, ray, t_min, t_max, t, normal);
+    case PRIMITIVE_BOX:
+        return intersect_box(p, ray, t_min, t_max, t, normal);
+    case PRIMITIVE_ORIENTED_BOX:
+        return intersect_oriented_box(p, ray, t_min, t_max, t, normal);
+    case PRIMITIVE_CYLINDER:
+        return intersect_cylinder(p, ray, t_min, t_max, t, normal);
+    case PRIMITIVE_CAPSULE:
+        return intersect_capsule(p, ray, t_min, t_max, t, normal);
+    case PRIMITIVE_TORUS:
+        return intersect_torus(p, ray, t_min, t_max, t, normal);
+    case PRIMITIVE_MESH:
+        return intersect_mesh(p, ray, t_min, t_max, t, normal);
+    default:
+        return intersect_sphere(p, ray, t_min, t_max, t, normal);
+    }
+}
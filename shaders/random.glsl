@@ -0,0 +1,12 @@
+// Random numbers shared by the pipelines, the Rust side is in `reference.rs`
+
+// PCG hash, see "Hash Functions for GPU Rendering" by Jarzynski and Olano
+uint pcg(inout uint state) {
+    state = state * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+float random(inout uint state) {
+    return float(pcg(state)) / 4294967296.0;
+}
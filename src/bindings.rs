@@ -0,0 +1,318 @@
+use std::{collections::HashMap, error::Error, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+use winit::event::{
+    ElementState, Event, KeyboardInput, ModifiersState, MouseButton, VirtualKeyCode, WindowEvent,
+};
+
+/// Something the app does when its bindings are pressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    RollLeft,
+    RollRight,
+    Sprint,
+    /// Switches the camera between flying and orbiting
+    ToggleOrbit,
+    /// Switches to the next pipeline
+    NextPipeline,
+    /// Saves the frame as a PNG
+    Screenshot,
+    Quit,
+}
+
+/// A key or a mouse button
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Input {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+}
+
+/// An input along with the modifiers that have to be held with it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Binding {
+    pub input: Input,
+    #[serde(default)]
+    pub shift: bool,
+    #[serde(default)]
+    pub ctrl: bool,
+    #[serde(default)]
+    pub alt: bool,
+    #[serde(default)]
+    pub logo: bool,
+}
+
+impl Binding {
+    pub fn key(key: VirtualKeyCode) -> Self {
+        Self {
+            input: Input::Key(key),
+            shift: false,
+            ctrl: false,
+            alt: false,
+            logo: false,
+        }
+    }
+
+    pub fn mouse(button: MouseButton) -> Self {
+        Self {
+            input: Input::Mouse(button),
+            shift: false,
+            ctrl: false,
+            alt: false,
+            logo: false,
+        }
+    }
+
+    fn modifiers(&self) -> ModifiersState {
+        let mut modifiers = ModifiersState::empty();
+        modifiers.set(ModifiersState::SHIFT, self.shift);
+        modifiers.set(ModifiersState::CTRL, self.ctrl);
+        modifiers.set(ModifiersState::ALT, self.alt);
+        modifiers.set(ModifiersState::LOGO, self.logo);
+        modifiers
+    }
+}
+
+/// The inputs bound to each action, stored on disk as RON, see `bindings.ron`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Bindings(pub HashMap<Action, Vec<Binding>>);
+
+impl Bindings {
+    /// Reads bindings from a RON file, actions it leaves out keep their default bindings
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let source = fs::read_to_string(path)?;
+        Self::parse(&source)
+    }
+
+    pub fn parse(source: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let Self(overrides) = ron::from_str(source)?;
+        let mut bindings = Self::default();
+        bindings.0.extend(overrides);
+        Ok(bindings)
+    }
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use VirtualKeyCode::*;
+
+        let keys = |keys: &[VirtualKeyCode]| keys.iter().copied().map(Binding::key).collect();
+        Self(HashMap::from([
+            (Action::MoveForward, keys(&[W, Up])),
+            (Action::MoveBackward, keys(&[S, Down])),
+            (Action::MoveLeft, keys(&[A, Left])),
+            (Action::MoveRight, keys(&[D, Right])),
+            (Action::MoveUp, keys(&[Space])),
+            (Action::MoveDown, keys(&[C])),
+            (Action::RollLeft, keys(&[Q])),
+            (Action::RollRight, keys(&[E])),
+            (Action::Sprint, keys(&[LShift])),
+            (Action::ToggleOrbit, keys(&[O])),
+            (Action::NextPipeline, keys(&[Tab])),
+            (
+                Action::Screenshot,
+                vec![
+                    Binding::key(F12),
+                    Binding {
+                        ctrl: true,
+                        ..Binding::key(S)
+                    },
+                ],
+            ),
+            (Action::Quit, keys(&[Escape])),
+        ]))
+    }
+}
+
+/// Turns window input into actions, following the bindings
+#[derive(Debug, Clone)]
+pub struct ActionMapper {
+    bindings: Bindings,
+    modifiers: ModifiersState,
+    /// The actions each held input started, ended when it's released
+    pressed: HashMap<Input, Vec<Action>>,
+    /// How many held inputs each action is held by
+    held: HashMap<Action, usize>,
+}
+
+impl ActionMapper {
+    pub fn new(bindings: Bindings) -> Self {
+        Self {
+            bindings,
+            modifiers: ModifiersState::empty(),
+            pressed: HashMap::new(),
+            held: HashMap::new(),
+        }
+    }
+
+    /// The actions pressed, true, or released, false, by the event
+    pub fn handle_event<T>(&mut self, event: &Event<T>) -> Vec<(Action, bool)> {
+        let Event::WindowEvent { event, .. } = event else {
+            return vec![];
+        };
+
+        match event {
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = *modifiers;
+                vec![]
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => self.input(Input::Key(*key), *state),
+            WindowEvent::MouseInput { state, button, .. } => {
+                self.input(Input::Mouse(*button), *state)
+            }
+            // Releases would be missed while the window isn't focused
+            WindowEvent::Focused(false) => self.release_all(),
+            _ => vec![],
+        }
+    }
+
+    fn input(&mut self, input: Input, state: ElementState) -> Vec<(Action, bool)> {
+        match state {
+            ElementState::Pressed => self.press(input),
+            ElementState::Released => self.release(input),
+        }
+    }
+
+    /// Presses the actions bound to `input` with the held modifiers. The bindings needing
+    /// the most modifiers win, so Ctrl+S doesn't also press what S alone is bound to.
+    pub fn press(&mut self, input: Input) -> Vec<(Action, bool)> {
+        // Keys repeat while held
+        if self.pressed.contains_key(&input) {
+            return vec![];
+        }
+
+        let matching = self
+            .bindings
+            .0
+            .iter()
+            .flat_map(|(action, bindings)| bindings.iter().map(move |b| (*action, b)))
+            .filter(|(_, b)| b.input == input && self.modifiers.contains(b.modifiers()))
+            .map(|(action, b)| (action, b.modifiers().bits().count_ones()))
+            .collect::<Vec<_>>();
+        let most = matching.iter().map(|(_, count)| *count).max();
+        let mut actions = matching
+            .into_iter()
+            .filter(|(_, count)| Some(*count) == most)
+            .map(|(action, _)| action)
+            .collect::<Vec<_>>();
+        actions.dedup();
+
+        let mut changes = vec![];
+        for action in &actions {
+            let held = self.held.entry(*action).or_default();
+            *held += 1;
+            if *held == 1 {
+                changes.push((*action, true));
+            }
+        }
+        self.pressed.insert(input, actions);
+        changes
+    }
+
+    pub fn release(&mut self, input: Input) -> Vec<(Action, bool)> {
+        let mut changes = vec![];
+        for action in self.pressed.remove(&input).unwrap_or_default() {
+            let held = self.held.entry(action).or_default();
+            *held = held.saturating_sub(1);
+            if *held == 0 {
+                changes.push((action, false));
+            }
+        }
+        changes
+    }
+
+    pub fn set_modifiers(&mut self, modifiers: ModifiersState) {
+        self.modifiers = modifiers;
+    }
+
+    pub fn is_held(&self, action: Action) -> bool {
+        self.held.get(&action).is_some_and(|held| *held > 0)
+    }
+
+    fn release_all(&mut self) -> Vec<(Action, bool)> {
+        let inputs = self.pressed.keys().copied().collect::<Vec<_>>();
+        inputs
+            .into_iter()
+            .flat_map(|input| self.release(input))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_shipped_bindings_are_the_defaults() {
+        let shipped = Bindings::parse(include_str!("../bindings.ron")).unwrap();
+        assert_eq!(shipped, Bindings::default());
+    }
+
+    #[test]
+    fn files_override_some_actions() {
+        let bindings = Bindings::parse(
+            "{ MoveForward: [(input: Key(I)), (input: Mouse(Right))], Quit: [(input: Key(Q), ctrl: true)] }",
+        )
+        .unwrap();
+        assert_eq!(
+            bindings.0[&Action::MoveForward],
+            vec![
+                Binding::key(VirtualKeyCode::I),
+                Binding::mouse(MouseButton::Right)
+            ]
+        );
+        assert!(bindings.0[&Action::Quit][0].ctrl);
+        assert_eq!(
+            bindings.0[&Action::MoveBackward],
+            Bindings::default().0[&Action::MoveBackward]
+        );
+    }
+
+    #[test]
+    fn modifiers_pick_the_most_specific_binding() {
+        let mut mapper = ActionMapper::new(Bindings::default());
+        let s = Input::Key(VirtualKeyCode::S);
+
+        assert_eq!(mapper.press(s), vec![(Action::MoveBackward, true)]);
+        assert_eq!(mapper.release(s), vec![(Action::MoveBackward, false)]);
+
+        mapper.set_modifiers(ModifiersState::CTRL);
+        assert_eq!(mapper.press(s), vec![(Action::Screenshot, true)]);
+        // Letting go of Ctrl first still releases what the press started
+        mapper.set_modifiers(ModifiersState::empty());
+        assert_eq!(mapper.release(s), vec![(Action::Screenshot, false)]);
+
+        // Modifiers a binding doesn't ask for don't get in the way
+        mapper.set_modifiers(ModifiersState::SHIFT);
+        assert_eq!(mapper.press(s), vec![(Action::MoveBackward, true)]);
+    }
+
+    #[test]
+    fn actions_stay_held_until_every_input_is_released() {
+        let mut mapper = ActionMapper::new(Bindings::default());
+        let w = Input::Key(VirtualKeyCode::W);
+        let up = Input::Key(VirtualKeyCode::Up);
+
+        assert_eq!(mapper.press(w), vec![(Action::MoveForward, true)]);
+        assert_eq!(mapper.press(up), vec![]);
+        assert_eq!(mapper.press(w), vec![]);
+        assert_eq!(mapper.release(w), vec![]);
+        assert!(mapper.is_held(Action::MoveForward));
+        assert_eq!(mapper.release(up), vec![(Action::MoveForward, false)]);
+        assert!(!mapper.is_held(Action::MoveForward));
+    }
+}
@@ -0,0 +1,358 @@
+use std::{error::Error, fs, path::Path};
+
+use nalgebra_glm::{self as glm, Quat, Vec3};
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+
+use crate::Camera;
+
+/// Seconds between the keyframes a `PathRecorder` takes by default
+pub const DEFAULT_RECORD_INTERVAL: f32 = 0.1;
+
+/// The camera at a point in time along a `CameraPath`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    /// Seconds from the start of the path
+    pub time: f32,
+    /// Written like the camera of a scene, so it can also be given with `look_at`
+    pub camera: Camera,
+}
+
+/// A camera moving through the scene, stored on disk as RON, see `scenes/flythrough.ron`.
+/// Positions follow a Catmull-Rom spline through the keyframes, orientations are slerped
+/// and the other parameters of the camera are blended linearly.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CameraPath {
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let source = fs::read_to_string(path)?;
+        Self::parse(&source)
+    }
+
+    pub fn parse(source: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let path: Self = ron::from_str(source)?;
+        path.validate()?;
+        Ok(path)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let source = ron::ser::to_string_pretty(self, PrettyConfig::default())?;
+        fs::write(path, source)?;
+        Ok(())
+    }
+
+    /// Checks the path has keyframes and that their times increase
+    pub fn validate(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.keyframes.is_empty() {
+            return Err("camera path has no keyframes".into());
+        }
+        for (i, pair) in self.keyframes.windows(2).enumerate() {
+            if pair[1].time <= pair[0].time {
+                return Err(format!("keyframe {} isn't later than the one before", i + 1).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Seconds until the last keyframe
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    /// The camera `time` seconds along the path, held at the first and last keyframes
+    /// outside of it. None if the path has no keyframes.
+    pub fn sample(&self, time: f32) -> Option<Camera> {
+        let keyframes = &self.keyframes;
+        let last = keyframes.len().checked_sub(1)?;
+        // The keyframe the segment holding `time` starts at
+        let i = keyframes
+            .iter()
+            .rposition(|keyframe| keyframe.time <= time)
+            .unwrap_or(0)
+            .min(last.saturating_sub(1));
+        let (a, b) = (&keyframes[i], &keyframes[(i + 1).min(last)]);
+        if time <= a.time || a.time == b.time {
+            return Some(a.camera.clone());
+        }
+        if time >= b.time {
+            return Some(b.camera.clone());
+        }
+
+        let t = (time - a.time) / (b.time - a.time);
+        let lerp = |x: f32, y: f32| x + (y - x) * t;
+        let position = catmull_rom(keyframes, i, time);
+        Some(Camera {
+            position,
+            orientation: slerp(&a.camera.orientation, &b.camera.orientation, t),
+            fov: lerp(a.camera.fov, b.camera.fov),
+            near: lerp(a.camera.near, b.camera.near),
+            far: lerp(a.camera.far, b.camera.far),
+            aperture: lerp(a.camera.aperture, b.camera.aperture),
+            focus_distance: lerp(a.camera.focus_distance, b.camera.focus_distance),
+            ..a.camera.clone()
+        })
+    }
+}
+
+// The position between keyframes i and i + 1 at `time`. Tangents are the slopes between the
+// neighbouring keyframes, so unevenly spaced keyframes still move smoothly.
+fn catmull_rom(keyframes: &[Keyframe], i: usize, time: f32) -> Vec3 {
+    let last = keyframes.len() - 1;
+    let point = |j: usize| (keyframes[j].time, keyframes[j].camera.position);
+    let tangent = |j: usize| {
+        let (t0, p0) = point(j.saturating_sub(1));
+        let (t1, p1) = point((j + 1).min(last));
+        (p1 - p0) / (t1 - t0)
+    };
+
+    let ((t0, p0), (t1, p1)) = (point(i), point(i + 1));
+    let span = t1 - t0;
+    let t = (time - t0) / span;
+    let (t2, t3) = (t * t, t * t * t);
+    // Cubic Hermite basis
+    p0 * (2.0 * t3 - 3.0 * t2 + 1.0)
+        + tangent(i) * span * (t3 - 2.0 * t2 + t)
+        + p1 * (-2.0 * t3 + 3.0 * t2)
+        + tangent(i + 1) * span * (t3 - t2)
+}
+
+// Spherical interpolation the short way round, falling back to a normalised blend where the
+// orientations are too close for the angle between them to be accurate
+fn slerp(a: &Quat, b: &Quat, t: f32) -> Quat {
+    let mut b = *b;
+    let mut cos = glm::quat_dot(a, &b);
+    if cos < 0.0 {
+        b = -b;
+        cos = -cos;
+    }
+    if cos > 0.9995 {
+        return glm::quat_normalize(&(a * (1.0 - t) + b * t));
+    }
+
+    let angle = cos.acos();
+    let sin = angle.sin();
+    a * (((1.0 - t) * angle).sin() / sin) + b * ((t * angle).sin() / sin)
+}
+
+/// Records the poses of a camera into a `CameraPath`, a keyframe every `interval` seconds
+#[derive(Debug, Clone)]
+pub struct PathRecorder {
+    path: CameraPath,
+    interval: f32,
+    time: f32,
+}
+
+impl PathRecorder {
+    pub fn new(interval: f32) -> Self {
+        Self {
+            path: CameraPath::default(),
+            interval,
+            time: 0.0,
+        }
+    }
+
+    /// Advances the clock by `dt` seconds, keeping `camera` if a keyframe is due
+    pub fn record(&mut self, dt: f32, camera: &Camera) {
+        self.time += dt;
+        let due = match self.path.keyframes.last() {
+            Some(last) => self.time - last.time >= self.interval,
+            None => true,
+        };
+        if due {
+            self.path.keyframes.push(Keyframe {
+                time: self.time,
+                camera: camera.clone(),
+            });
+        }
+    }
+
+    /// The path recorded so far, starting at zero seconds
+    pub fn path(&self) -> CameraPath {
+        let start = self.path.keyframes.first().map_or(0.0, |first| first.time);
+        CameraPath {
+            keyframes: self
+                .path
+                .keyframes
+                .iter()
+                .map(|keyframe| Keyframe {
+                    time: keyframe.time - start,
+                    camera: keyframe.camera.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Default for PathRecorder {
+    fn default() -> Self {
+        Self::new(DEFAULT_RECORD_INTERVAL)
+    }
+}
+
+/// Plays a `CameraPath` back a fixed step at a time, one camera per frame, so every run
+/// draws the same frames whatever the frame rate
+#[derive(Debug, Clone)]
+pub struct PathPlayer {
+    path: CameraPath,
+    timestep: f32,
+    frame: u32,
+}
+
+impl PathPlayer {
+    /// Steps through `path` at `fps` frames per second of path time
+    pub fn new(path: CameraPath, fps: f32) -> Self {
+        Self {
+            path,
+            timestep: 1.0 / fps,
+            frame: 0,
+        }
+    }
+
+    /// How many frames have been played
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// How many frames the whole path takes, including both ends
+    pub fn frame_count(&self) -> u32 {
+        (self.path.duration() / self.timestep + 1e-3).floor() as u32 + 1
+    }
+}
+
+impl Iterator for PathPlayer {
+    type Item = Camera;
+
+    fn next(&mut self) -> Option<Camera> {
+        if self.frame >= self.frame_count() {
+            return None;
+        }
+        // Multiplied rather than summed, so no error builds up
+        let camera = self.path.sample(self.frame as f32 * self.timestep)?;
+        self.frame += 1;
+        Some(camera)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm::vec3;
+
+    fn keyframe(time: f32, position: Vec3) -> Keyframe {
+        Keyframe {
+            time,
+            camera: Camera {
+                position,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).magnitude() < 1e-4, "{a} != {b}");
+    }
+
+    #[test]
+    fn positions_pass_through_the_keyframes_smoothly() {
+        let path = CameraPath {
+            keyframes: vec![
+                keyframe(0.0, vec3(0.0, 0.0, 0.0)),
+                keyframe(1.0, vec3(1.0, 0.0, 0.0)),
+                keyframe(3.0, vec3(1.0, 2.0, 0.0)),
+            ],
+        };
+        let at = |time: f32| path.sample(time).unwrap().position;
+
+        for keyframe in &path.keyframes {
+            assert_close(at(keyframe.time), keyframe.camera.position);
+        }
+        // Held at the ends
+        assert_close(at(-1.0), vec3(0.0, 0.0, 0.0));
+        assert_close(at(5.0), vec3(1.0, 2.0, 0.0));
+
+        // No kink at the middle keyframe, the velocity is the same on both sides
+        let h = 1e-2;
+        let before = (at(1.0) - at(1.0 - h)) / h;
+        let after = (at(1.0 + h) - at(1.0)) / h;
+        assert!((before - after).magnitude() < 0.05, "{before} != {after}");
+
+        // Evenly spaced keyframes on a line are followed at a constant speed
+        let line = CameraPath {
+            keyframes: (0..4)
+                .map(|i| keyframe(i as f32, vec3(i as f32, 0.0, 0.0)))
+                .collect(),
+        };
+        assert_close(line.sample(1.25).unwrap().position, vec3(1.25, 0.0, 0.0));
+    }
+
+    #[test]
+    fn orientations_are_slerped_the_short_way() {
+        let quarter = glm::quat_angle_axis(std::f32::consts::FRAC_PI_2, &Vec3::y());
+        let half = slerp(&Quat::identity(), &quarter, 0.5);
+        let expected = glm::quat_angle_axis(std::f32::consts::FRAC_PI_4, &Vec3::y());
+        assert!((half.coords - expected.coords).magnitude() < 1e-5);
+
+        // The same rotation with its sign flipped, which is the long way round otherwise
+        let flipped = slerp(&Quat::identity(), &-quarter, 0.5);
+        assert!((flipped.coords.abs() - expected.coords.abs()).magnitude() < 1e-5);
+
+        // Nearly equal orientations don't divide by a vanishing angle
+        let same = slerp(&quarter, &quarter, 0.3);
+        assert!((same.coords - quarter.coords).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn recordings_play_back_at_a_fixed_timestep() {
+        let mut recorder = PathRecorder::new(0.5);
+        for i in 0..=20 {
+            let camera = Camera {
+                position: vec3(i as f32, 0.0, 0.0),
+                ..Default::default()
+            };
+            // An uneven frame rate
+            recorder.record(if i % 2 == 0 { 0.1 } else { 0.15 }, &camera);
+        }
+        let path = recorder.path();
+        assert!(path.validate().is_ok());
+        assert_eq!(path.keyframes[0].time, 0.0);
+
+        let written = CameraPath::parse(&ron::to_string(&path).unwrap()).unwrap();
+        assert_eq!(written.keyframes.len(), path.keyframes.len());
+
+        let player = PathPlayer::new(path.clone(), 10.0);
+        let count = player.frame_count();
+        assert_eq!(count, (path.duration() * 10.0).floor() as u32 + 1);
+        let cameras = player.collect::<Vec<_>>();
+        assert_eq!(cameras.len(), count as usize);
+        assert_close(cameras[0].position, path.keyframes[0].camera.position);
+    }
+
+    #[test]
+    fn keyframes_are_written_by_hand() {
+        let path = CameraPath::parse(
+            "(keyframes: [
+                (time: 0.0, camera: (position: [0.0, 0.0, -5.0], look_at: [0.0, 0.0, 0.0])),
+                (time: 2.0, camera: (position: [-5.0, 0.0, 0.0], look_at: [0.0, 0.0, 0.0], fov: 1.0)),
+            ])",
+        )
+        .unwrap();
+        assert_eq!(path.duration(), 2.0);
+
+        // Halfway round, looking at the target from between the two keyframes
+        let camera = path.sample(1.0).unwrap();
+        let forward = (camera.rotation_matrix() * glm::vec4(0.0, 0.0, -1.0, 0.0)).xyz();
+        assert_close(forward, vec3(-1.0, 0.0, -1.0).normalize());
+
+        assert!(CameraPath::parse("(keyframes: [])").is_err());
+        assert!(CameraPath::parse(
+            "(keyframes: [
+                (time: 1.0, camera: (position: [0.0, 0.0, 0.0])),
+                (time: 1.0, camera: (position: [1.0, 0.0, 0.0])),
+            ])"
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,395 @@
+use std::f32::consts::FRAC_PI_2;
+
+use nalgebra_glm::{self as glm, vec3, Mat3, Vec3};
+use winit::event::{DeviceEvent, Event, MouseScrollDelta, WindowEvent};
+
+use crate::{Action, Camera};
+
+/// How a `CameraController` moves the camera
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControllerMode {
+    /// Flying freely, moving along the axes of the camera
+    Fly,
+    /// Circling a target the camera keeps looking at, moving pans the target
+    Orbit,
+}
+
+/// A movement the controller applies for as long as it's held
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Motion {
+    Forward,
+    Backward,
+    Left,
+    Right,
+    Up,
+    Down,
+    RollLeft,
+    RollRight,
+    /// Multiplies the speed by `ControllerSettings::sprint_multiplier`
+    Sprint,
+}
+
+impl Motion {
+    const COUNT: usize = 9;
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// How fast and how smoothly a `CameraController` moves
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ControllerSettings {
+    /// Units per second at full speed
+    pub speed: f32,
+    pub sprint_multiplier: f32,
+    /// How quickly the velocity reaches full speed, per second
+    pub acceleration: f32,
+    /// How quickly the velocity dies down once nothing is held, per second
+    pub damping: f32,
+    /// Radians turned per pixel of mouse motion
+    pub look_sensitivity: f32,
+    /// Radians rolled per second
+    pub roll_speed: f32,
+    /// Furthest the camera pitches up or down, in radians
+    pub max_pitch: f32,
+    /// Share of the orbit distance a scroll line zooms by
+    pub zoom_speed: f32,
+}
+
+impl Default for ControllerSettings {
+    fn default() -> Self {
+        Self {
+            speed: 10.0,
+            sprint_multiplier: 3.0,
+            acceleration: 12.0,
+            damping: 8.0,
+            look_sensitivity: 0.003,
+            roll_speed: 1.5,
+            max_pitch: FRAC_PI_2 - 0.01,
+            zoom_speed: 0.1,
+        }
+    }
+}
+
+/// Turns input into camera movement, flying freely or orbiting a target
+#[derive(Debug, Clone)]
+pub struct CameraController {
+    pub settings: ControllerSettings,
+    mode: ControllerMode,
+    /// World position of the camera when flying, of the target when orbiting
+    position: Vec3,
+    /// How far the camera is from the target when orbiting
+    distance: f32,
+    yaw: f32,
+    /// Positive when looking down
+    pitch: f32,
+    roll: f32,
+    velocity: Vec3,
+    held: [bool; Motion::COUNT],
+    /// The camera the controller started from, its projection is kept
+    lens: Camera,
+}
+
+impl CameraController {
+    /// Flies from the pose of `camera`
+    pub fn new(camera: &Camera) -> Self {
+        let rotation = camera.rotation_matrix().fixed_view::<3, 3>(0, 0).into();
+        let (yaw, pitch, roll) = yaw_pitch_roll(&rotation);
+        Self {
+            settings: ControllerSettings::default(),
+            mode: ControllerMode::Fly,
+            position: -camera.position,
+            distance: 5.0,
+            yaw,
+            pitch,
+            roll,
+            velocity: Vec3::zeros(),
+            held: [false; Motion::COUNT],
+            lens: camera.clone(),
+        }
+    }
+
+    pub fn mode(&self) -> ControllerMode {
+        self.mode
+    }
+
+    /// Switches modes without moving the camera, orbiting starts around the point
+    /// `distance` ahead of it
+    pub fn set_mode(&mut self, mode: ControllerMode) {
+        match (self.mode, mode) {
+            (ControllerMode::Fly, ControllerMode::Orbit) => {
+                self.position += self.forward() * self.distance;
+            }
+            (ControllerMode::Orbit, ControllerMode::Fly) => {
+                self.position = self.eye();
+            }
+            _ => {}
+        }
+        self.mode = mode;
+    }
+
+    pub fn set_motion(&mut self, motion: Motion, held: bool) {
+        self.held[motion.index()] = held;
+    }
+
+    /// Turns the camera by a mouse motion in pixels, independent of the frame time
+    pub fn look(&mut self, dx: f32, dy: f32) {
+        let max_pitch = self.settings.max_pitch;
+        self.yaw += dx * self.settings.look_sensitivity;
+        self.pitch =
+            (self.pitch + dy * self.settings.look_sensitivity).clamp(-max_pitch, max_pitch);
+    }
+
+    /// Moves towards the target by `lines` of scrolling when orbiting
+    pub fn zoom(&mut self, lines: f32) {
+        let scale = (1.0 - self.settings.zoom_speed).powf(lines);
+        self.distance = (self.distance * scale).max(0.01);
+    }
+
+    /// Applies mouse motion and scrolling, keys and buttons come in through `apply`
+    pub fn handle_event<T>(&mut self, event: &Event<T>) {
+        match event {
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta: (x, y) },
+                ..
+            } => self.look(*x as f32, *y as f32),
+
+            Event::WindowEvent {
+                event: WindowEvent::MouseWheel { delta, .. },
+                ..
+            } => self.zoom(match delta {
+                MouseScrollDelta::LineDelta(_, y) => *y,
+                MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+            }),
+            _ => {}
+        }
+    }
+
+    /// Applies an action being pressed or released, others than moving the camera are ignored
+    pub fn apply(&mut self, action: Action, pressed: bool) {
+        let motion = match action {
+            Action::MoveForward => Motion::Forward,
+            Action::MoveBackward => Motion::Backward,
+            Action::MoveLeft => Motion::Left,
+            Action::MoveRight => Motion::Right,
+            Action::MoveUp => Motion::Up,
+            Action::MoveDown => Motion::Down,
+            Action::RollLeft => Motion::RollLeft,
+            Action::RollRight => Motion::RollRight,
+            Action::Sprint => Motion::Sprint,
+            Action::ToggleOrbit if pressed => {
+                self.set_mode(match self.mode {
+                    ControllerMode::Fly => ControllerMode::Orbit,
+                    ControllerMode::Orbit => ControllerMode::Fly,
+                });
+                return;
+            }
+            _ => return,
+        };
+        self.set_motion(motion, pressed);
+    }
+
+    /// Advances the movement by `dt` seconds and returns the camera to draw with
+    pub fn update(&mut self, dt: f32) -> Camera {
+        let axis = |positive: Motion, negative: Motion| {
+            self.held[positive.index()] as i32 as f32 - self.held[negative.index()] as i32 as f32
+        };
+
+        let roll = axis(Motion::RollRight, Motion::RollLeft);
+        self.roll += roll * self.settings.roll_speed * dt;
+
+        // The movement held, along the axes of the camera
+        let rotation = self.rotation();
+        let local = vec3(
+            axis(Motion::Right, Motion::Left),
+            axis(Motion::Up, Motion::Down),
+            axis(Motion::Forward, Motion::Backward),
+        );
+        let mut target_velocity = Vec3::zeros();
+        if local != Vec3::zeros() {
+            let speed = match self.held[Motion::Sprint.index()] {
+                true => self.settings.speed * self.settings.sprint_multiplier,
+                false => self.settings.speed,
+            };
+            // The image is mirrored, screen right is -X of the camera
+            let direction = rotation * vec3(-local.x, local.y, -local.z);
+            target_velocity = direction.normalize() * speed;
+        }
+
+        let rate = match local != Vec3::zeros() {
+            true => self.settings.acceleration,
+            false => self.settings.damping,
+        };
+        self.velocity = approach(self.velocity, target_velocity, rate, dt);
+        self.position += self.velocity * dt;
+
+        self.camera()
+    }
+
+    /// The camera at the current pose
+    pub fn camera(&self) -> Camera {
+        Camera {
+            position: -self.eye(),
+            orientation: glm::mat3_to_quat(&self.rotation()),
+            ..self.lens.clone()
+        }
+    }
+
+    fn rotation(&self) -> Mat3 {
+        rotation(self.yaw, self.pitch, self.roll)
+    }
+
+    /// Where the camera looks, the -Z axis of its rotation
+    fn forward(&self) -> Vec3 {
+        self.rotation() * vec3(0.0, 0.0, -1.0)
+    }
+
+    fn eye(&self) -> Vec3 {
+        match self.mode {
+            ControllerMode::Fly => self.position,
+            ControllerMode::Orbit => self.position - self.forward() * self.distance,
+        }
+    }
+}
+
+// Turns about Y by the yaw, then about X by the pitch and about Z by the roll
+fn rotation(yaw: f32, pitch: f32, roll: f32) -> Mat3 {
+    let about = |axis: Vec3, angle: f32| {
+        nalgebra_glm::rotation(angle, &axis)
+            .fixed_view::<3, 3>(0, 0)
+            .into_owned()
+    };
+    about(Vec3::y(), yaw) * about(Vec3::x(), -pitch) * about(Vec3::z(), roll)
+}
+
+// The inverse of `rotation`
+fn yaw_pitch_roll(rotation: &Mat3) -> (f32, f32, f32) {
+    let sin_pitch = rotation.m23.clamp(-1.0, 1.0);
+    // Looking straight up or down, yaw and roll turn about the same axis
+    if sin_pitch.abs() > 1.0 - 1e-6 {
+        return ((-rotation.m31).atan2(rotation.m11), sin_pitch.asin(), 0.0);
+    }
+    (
+        rotation.m13.atan2(rotation.m33),
+        sin_pitch.asin(),
+        rotation.m21.atan2(rotation.m22),
+    )
+}
+
+// Eases `current` towards `target` at `rate` per second, the same whatever the frame rate
+fn approach(current: Vec3, target: Vec3, rate: f32, dt: f32) -> Vec3 {
+    current + (target - current) * (1.0 - (-rate * dt).exp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controller() -> CameraController {
+        CameraController::new(&Camera::default())
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).magnitude() < 1e-4, "{a} != {b}");
+    }
+
+    #[test]
+    fn rotation_round_trips_through_the_camera() {
+        let camera = Camera {
+            position: vec3(1.0, 2.0, 3.0),
+            orientation: glm::mat3_to_quat(&rotation(0.4, -0.3, 0.2)),
+            fov: 0.5,
+            ..Default::default()
+        };
+        let controller = CameraController::new(&camera);
+        assert!((controller.yaw - 0.4).abs() < 1e-5);
+        assert!((controller.pitch + 0.3).abs() < 1e-5);
+        assert!((controller.roll - 0.2).abs() < 1e-5);
+        assert_close(controller.camera().position, camera.position);
+        let difference = controller.camera().rotation_matrix() - camera.rotation_matrix();
+        assert!(difference.abs().max() < 1e-5);
+        assert_eq!(controller.camera().fov, camera.fov);
+    }
+
+    #[test]
+    fn moving_follows_where_the_camera_looks() {
+        let mut controller = controller();
+        controller.look(
+            0.0,
+            (FRAC_PI_2 / 2.0) / controller.settings.look_sensitivity,
+        );
+        controller.set_motion(Motion::Forward, true);
+        for _ in 0..600 {
+            controller.update(1.0 / 60.0);
+        }
+
+        // Pitched down 45 degrees, flying forward also descends
+        let world = controller.eye();
+        assert!(world.y < -10.0 && world.z < -10.0);
+        assert!((world.y - world.z).abs() < 0.5);
+        assert!(world.x.abs() < 1e-4);
+    }
+
+    #[test]
+    fn velocity_eases_in_and_out() {
+        let mut controller = controller();
+        controller.set_motion(Motion::Up, true);
+        controller.update(0.05);
+        let early = controller.velocity.magnitude();
+        assert!(early > 0.0 && early < controller.settings.speed);
+
+        controller.update(5.0);
+        assert_close(
+            controller.velocity,
+            vec3(0.0, controller.settings.speed, 0.0),
+        );
+
+        controller.set_motion(Motion::Sprint, true);
+        controller.update(5.0);
+        let sprint = controller.settings.speed * controller.settings.sprint_multiplier;
+        assert_close(controller.velocity, vec3(0.0, sprint, 0.0));
+
+        controller.set_motion(Motion::Up, false);
+        controller.update(5.0);
+        assert!(controller.velocity.magnitude() < 1e-3);
+    }
+
+    #[test]
+    fn easing_does_not_depend_on_the_frame_rate() {
+        let once = approach(Vec3::zeros(), vec3(1.0, 0.0, 0.0), 4.0, 0.5);
+        let mut stepped = Vec3::zeros();
+        for _ in 0..50 {
+            stepped = approach(stepped, vec3(1.0, 0.0, 0.0), 4.0, 0.01);
+        }
+        assert_close(once, stepped);
+    }
+
+    #[test]
+    fn pitch_is_clamped() {
+        let mut controller = controller();
+        controller.look(0.0, 1e6);
+        assert_eq!(controller.pitch, controller.settings.max_pitch);
+        controller.look(0.0, -1e7);
+        assert_eq!(controller.pitch, -controller.settings.max_pitch);
+    }
+
+    #[test]
+    fn orbiting_keeps_looking_at_the_target() {
+        let mut controller = controller();
+        controller.set_mode(ControllerMode::Orbit);
+        let target = controller.position;
+        assert_close(target, vec3(0.0, 0.0, -5.0));
+        assert_close(controller.eye(), Vec3::zeros());
+
+        controller.look(500.0, 200.0);
+        controller.zoom(2.0);
+        let eye = controller.eye();
+        assert!(((eye - target).magnitude() - 5.0 * 0.81).abs() < 1e-4);
+        assert_close(eye + controller.forward() * controller.distance, target);
+
+        // Flying again leaves the camera where it was
+        controller.set_mode(ControllerMode::Fly);
+        assert_close(controller.eye(), eye);
+    }
+}
@@ -0,0 +1,50 @@
+//! Wreckage is a ray tracer running on Vulkan compute shaders.
+//!
+//! A [`RenderingContext`] owns the Vulkan instance and device. Renderers are
+//! built on top of it, either presenting to a window surface or drawing
+//! headless frames that are read back with [`Renderer::capture`]:
+//!
+//! ```no_run
+//! use vulkano::{device::DeviceExtensions, instance::InstanceExtensions, VulkanLibrary};
+//! use wreckage::{NaiveRenderer, Renderer, RenderingContext, Scene};
+//!
+//! let library = VulkanLibrary::new().unwrap();
+//! let ctx = RenderingContext::new(
+//!     library,
+//!     InstanceExtensions::empty(),
+//!     DeviceExtensions::empty(),
+//! )
+//! .unwrap();
+//!
+//! let scene = Scene::grid();
+//! let mut renderer = NaiveRenderer::headless(ctx, [800, 600], &scene).unwrap();
+//! renderer.capture().unwrap().save("frame.png").unwrap();
+//! ```
+//!
+//! [`RenderingContext::builder`] picks a specific device with a
+//! [`DeviceSelector`], and [`RenderingContext::devices`] reports what each
+//! device supports. In debug mode it enables [`VALIDATION_LAYER`] and logs
+//! its messages.
+//!
+//! Fallible calls return a [`WreckageError`], telling apart a missing Vulkan
+//! library or device from errors while drawing.
+//!
+//! Without Vulkan, [`ReferenceRenderer`] draws the same image on the CPU.
+//!
+//! A [`CameraController`] turns window input into a [`Camera`], flying freely
+//! or orbiting a target. It's driven by [`Action`]s, which an [`ActionMapper`]
+//! presses following [`Bindings`] that can be loaded from a file.
+//!
+//! A [`PathRecorder`] keeps the poses of a camera as a [`CameraPath`], which a
+//! [`PathPlayer`] plays back smoothly at a fixed timestep.
+
+pub mod bindings;
+pub mod camera_path;
+pub mod controller;
+pub mod renderer;
+
+pub use bindings::*;
+pub use camera_path::*;
+pub use controller::*;
+
+pub use renderer::prelude::*;
@@ -1,28 +1,140 @@
-mod renderer;
-use std::{error::Error, sync::Arc, time};
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    process,
+    sync::Arc,
+    time,
+};
+
+use clap::Parser;
 
-use nalgebra_glm::{rotate_vec3, vec3, Vec3};
-pub use renderer::prelude::*;
+use wreckage::{
+    Action, ActionMapper, Bindings, CameraController, CameraPath, DeviceSelector, PathPlayer,
+    PathRecorder, PipelineRegistry, ReferenceRenderer, RenderSettings, RenderTarget, Renderer,
+    RenderingContext, Scene, WreckageError, DEFAULT_FRAMES_IN_FLIGHT,
+};
 
-use log::{debug, info, warn};
+use log::{debug, error, info, warn};
 use vulkano::{device::DeviceExtensions, VulkanLibrary};
 use vulkano_win::create_surface_from_winit;
 use winit::{
-    event::{DeviceEvent, ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+    event::{Event, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
 
-use crate::renderer::prelude::renderer::RenderingContext;
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// Scene file to load, RON or glTF, the built-in sphere grid is used if omitted
+    scene: Option<PathBuf>,
+
+    /// Render a single frame without opening a window
+    #[arg(long)]
+    headless: bool,
+
+    /// Where to write the frame rendered in headless mode
+    #[arg(long, default_value = "frame.png", requires = "headless")]
+    output: PathBuf,
+
+    /// Width of the frame rendered in headless mode
+    #[arg(long, default_value_t = 800, requires = "headless")]
+    width: u32,
+
+    /// Height of the frame rendered in headless mode
+    #[arg(long, default_value_t = 600, requires = "headless")]
+    height: u32,
+
+    /// How many frames the CPU may prepare ahead of the GPU, at most 3
+    #[arg(long, default_value_t = DEFAULT_FRAMES_IN_FLIGHT)]
+    frames_in_flight: usize,
+
+    /// Render headless frames on the CPU instead of through Vulkan
+    #[arg(long, requires = "headless")]
+    cpu: bool,
+
+    /// Fail instead of rendering headless frames on the CPU when Vulkan can't be used
+    #[arg(long, requires = "headless", conflicts_with = "cpu")]
+    gpu_only: bool,
+
+    /// Pipeline to render with, Tab cycles through the others while running
+    #[arg(long, default_value = "naive")]
+    pipeline: String,
+
+    /// Samples per pixel the path tracer accumulates before it stops, 0 keeps refining
+    #[arg(long, default_value_t = 0)]
+    samples: u32,
+
+    /// Samples per pixel the path tracer takes every frame
+    #[arg(long, default_value_t = 1)]
+    samples_per_frame: u32,
+
+    /// Most bounces of a path in the path tracer
+    #[arg(long, default_value_t = 8)]
+    max_depth: u32,
+
+    /// Device to render on, by index, UUID or part of its name, see --list-gpus
+    #[arg(long)]
+    gpu: Option<DeviceSelector>,
+
+    /// Enable the Vulkan validation layer and log its messages
+    #[arg(long)]
+    validation: bool,
+
+    /// RON file rebinding the keys and mouse buttons of actions, see bindings.ron
+    #[arg(long)]
+    bindings: Option<PathBuf>,
 
-pub fn is_pressed(state: ElementState) -> bool {
-    match state {
-        ElementState::Pressed => true,
-        ElementState::Released => false,
+    /// Print the devices and what they support, then exit
+    #[arg(long)]
+    list_gpus: bool,
+
+    /// Record the camera to a RON file when the window closes, see scenes/flythrough.ron
+    #[arg(long, conflicts_with_all = ["play", "headless"])]
+    record: Option<PathBuf>,
+
+    /// Play a recorded or hand-written camera path back, headless mode writes every frame
+    #[arg(long)]
+    play: Option<PathBuf>,
+
+    /// Frames per second of path time played back, whatever the real frame rate
+    #[arg(long, default_value_t = 60.0, requires = "play")]
+    fps: f32,
+}
+
+/// Exit code of `--gpu-only` renders that found no Vulkan device to render on
+const NO_GPU_EXIT_CODE: i32 = 3;
+
+// Gives up on a headless render that would have fallen back to the CPU
+fn no_gpu(reason: impl std::fmt::Display) -> ! {
+    error!("{reason}, not rendering on the CPU with --gpu-only");
+    process::exit(NO_GPU_EXIT_CODE)
+}
+
+// The path of the frame of a headless playback, frame.png becomes frame-0001.png
+fn numbered(output: &Path, frame: usize) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = output.extension().unwrap_or_default().to_string_lossy();
+    output.with_file_name(format!("{stem}-{frame:04}.{extension}"))
+}
+
+// Saves a frame to the working directory, named after the time it was taken
+fn screenshot(renderer: &mut dyn Renderer) {
+    let time = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let path = PathBuf::from(format!("screenshot-{time}.png"));
+    match renderer.capture().map(|frame| frame.save(&path)) {
+        Ok(Ok(())) => info!("Saved screenshot to {}", path.display()),
+        Ok(Err(e)) => error!("Failed to save screenshot: {e}"),
+        Err(e) => error!("Failed to capture screenshot: {e}"),
     }
 }
 
 fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let args = Args::parse();
+
     env_logger::builder()
         .filter_level(log::LevelFilter::Trace)
         .init();
@@ -32,7 +144,120 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         git_version::git_version!(fallback = "unknown")
     );
 
-    let library = VulkanLibrary::new().expect("no local Vulkan library/DLL");
+    if args.list_gpus {
+        let library = VulkanLibrary::new().map_err(WreckageError::from)?;
+        for device in RenderingContext::devices(library)? {
+            println!("{device}");
+        }
+        return Ok(());
+    }
+
+    let scene = match &args.scene {
+        Some(path) => {
+            info!("Loading scene from {}", path.display());
+            Scene::load(path)?
+        }
+        None => Scene::grid(),
+    };
+
+    let path = match &args.play {
+        Some(path) => {
+            info!("Loading camera path from {}", path.display());
+            Some(CameraPath::load(path)?)
+        }
+        None => None,
+    };
+
+    let pipelines = PipelineRegistry::default();
+    if !pipelines.contains(&args.pipeline) {
+        let names = pipelines.names().collect::<Vec<_>>().join(", ");
+        return Err(format!(
+            "unknown pipeline {}, expected one of {names}",
+            args.pipeline
+        )
+        .into());
+    }
+
+    let settings = RenderSettings {
+        samples_per_frame: args.samples_per_frame,
+        max_depth: args.max_depth,
+        max_samples: args.samples,
+    };
+
+    if args.headless {
+        let size = [args.width, args.height];
+        let library = match args.cpu {
+            true => None,
+            false => match VulkanLibrary::new() {
+                Ok(library) => Some(library),
+                Err(e) if args.gpu_only => no_gpu(format!("No Vulkan library ({e})")),
+                Err(e) => {
+                    warn!("No Vulkan library ({e}), rendering on the CPU");
+                    None
+                }
+            },
+        };
+
+        let create = |library| -> Result<Box<dyn Renderer>, WreckageError> {
+            let mut builder = RenderingContext::builder(library).debug(args.validation);
+            if let Some(gpu) = &args.gpu {
+                builder = builder.device(gpu.clone());
+            }
+            let ctx = builder.build()?;
+            let mut renderer = pipelines
+                .create(&args.pipeline, ctx, RenderTarget::Headless(size), &scene)
+                .expect("pipeline was checked to exist")?;
+            renderer.set_settings(&settings);
+            Ok(renderer)
+        };
+        let mut renderer = match library.map(create) {
+            Some(Ok(renderer)) => Some(renderer),
+            Some(Err(e @ (WreckageError::NoSuitableDevice | WreckageError::NoQueueFamily))) => {
+                if args.gpu_only {
+                    no_gpu(e);
+                }
+                warn!("{e}, rendering on the CPU");
+                None
+            }
+            Some(Err(e)) => return Err(e.into()),
+            None => {
+                if args.pipeline != "naive" {
+                    warn!("The CPU only renders like the naive pipeline");
+                }
+                None
+            }
+        };
+        let mut reference = None;
+
+        let frames = match path {
+            Some(path) => PathPlayer::new(path, args.fps)
+                .enumerate()
+                .map(|(i, camera)| (numbered(&args.output, i), camera))
+                .collect(),
+            None => vec![(args.output.clone(), scene.camera.clone())],
+        };
+        for (output, camera) in frames {
+            let frame = match renderer.as_mut() {
+                Some(renderer) => {
+                    renderer.set_camera(&camera);
+                    renderer.capture()?
+                }
+                None => reference
+                    .get_or_insert_with(|| ReferenceRenderer::new(size, &scene))
+                    .render(&camera),
+            };
+            frame.save(&output)?;
+            info!("Wrote frame to {}", output.display());
+        }
+        return Ok(());
+    }
+
+    let bindings = match &args.bindings {
+        Some(path) => Bindings::load(path)?,
+        None => Bindings::default(),
+    };
+
+    let library = VulkanLibrary::new().map_err(WreckageError::from)?;
     let extension_count = library.extension_properties().len();
     info!("{extension_count} extensions supported");
 
@@ -42,14 +267,17 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         ..Default::default()
     };
 
-    let ctx = RenderingContext::new(library, instance_ext, device_ext)?;
+    let mut builder = RenderingContext::builder(library)
+        .instance_extensions(instance_ext)
+        .device_extensions(device_ext)
+        .debug(args.validation);
+    if let Some(gpu) = args.gpu {
+        builder = builder.device(gpu);
+    }
+    let ctx = builder.build()?;
 
     let event_loop = EventLoop::new();
-    let window = Arc::new(
-        WindowBuilder::new()
-            .with_resizable(false)
-            .build(&event_loop)?,
-    );
+    let window = Arc::new(WindowBuilder::new().build(&event_loop)?);
 
     window.set_cursor_visible(false);
     window
@@ -63,108 +291,147 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
 
     let surface = create_surface_from_winit(window, ctx.instance.clone())?;
 
-    let mut renderer = NaiveRenderer::new(ctx, surface);
+    let mut controller = CameraController::new(&scene.camera);
+    let mut player = path.map(|path| PathPlayer::new(path, args.fps));
+    let mut playback_start = None;
+    let record = args.record;
+    let mut recorder = record.as_ref().map(|_| PathRecorder::default());
+    let mut actions = ActionMapper::new(bindings);
+    let mut pipeline = args.pipeline;
+    let create_renderer = move |pipelines: &PipelineRegistry, pipeline: &str| {
+        let mut renderer = pipelines
+            .create(
+                pipeline,
+                ctx.clone(),
+                RenderTarget::Surface(surface.clone()),
+                &scene,
+            )
+            .expect("pipeline was checked to exist")?;
+        renderer.set_frames_in_flight(args.frames_in_flight)?;
+        renderer.set_settings(&settings);
+        Ok::<_, WreckageError>(renderer)
+    };
+    let mut renderer = Some(create_renderer(&pipelines, &pipeline)?);
 
     let mut frame_begin = time::Instant::now();
     let mut fps_counter = 0;
 
     let mut last_frame_time = time::Instant::now();
-    let mut dt = 0f32;
-    let speed = 10f32;
-
-    let mut forward_pressed = false;
-    let mut backward_pressed = false;
-    let mut left_pressed = false;
-    let mut right_pressed = false;
-
-    let mut yaw = 0f32;
-    let mut pitch = 0f32;
-    let look_speed = 0.6;
-
-    event_loop.run(move |event, _, control_flow| match event {
-        Event::WindowEvent {
-            event: WindowEvent::CloseRequested,
-            ..
-        } => {
-            *control_flow = ControlFlow::Exit;
-        }
-
-        Event::DeviceEvent {
-            event: DeviceEvent::MouseMotion { delta: (x, y) },
-            ..
-        } => {
-            yaw += x as f32 * dt * look_speed;
-            pitch += y as f32 * dt * look_speed;
-        }
 
-        Event::WindowEvent {
-            event:
-                WindowEvent::KeyboardInput {
-                    input:
-                        KeyboardInput {
-                            state,
-                            virtual_keycode,
-                            ..
-                        },
-                    ..
-                },
-            ..
-        } => match virtual_keycode {
-            Some(VirtualKeyCode::W) => {
-                forward_pressed = is_pressed(state);
+    event_loop.run(move |event, _, control_flow| {
+        controller.handle_event(&event);
+        for (action, pressed) in actions.handle_event(&event) {
+            controller.apply(action, pressed);
+            if !pressed {
+                continue;
             }
-            Some(VirtualKeyCode::S) => {
-                backward_pressed = is_pressed(state);
+
+            match action {
+                Action::NextPipeline => {
+                    let Some(next) = pipelines.next(&pipeline) else {
+                        continue;
+                    };
+                    info!("Switching to the {next} pipeline");
+                    // The old swapchain has to be released before the surface can take a new one
+                    drop(renderer.take());
+                    pipeline = next.to_string();
+                    match create_renderer(&pipelines, &pipeline) {
+                        Ok(next) => renderer = Some(next),
+                        Err(e) => {
+                            error!("Failed to create the {pipeline} pipeline: {e}");
+                            *control_flow = ControlFlow::Exit;
+                        }
+                    }
+                }
+                Action::Screenshot => {
+                    if let Some(renderer) = renderer.as_mut() {
+                        screenshot(renderer.as_mut());
+                    }
+                }
+                Action::Quit => *control_flow = ControlFlow::Exit,
+                _ => {}
             }
-            Some(VirtualKeyCode::A) => {
-                left_pressed = is_pressed(state);
+        }
+
+        match event {
+            Event::WindowEvent {
+                event: WindowEvent::CloseRequested,
+                ..
+            } => {
+                *control_flow = ControlFlow::Exit;
             }
-            Some(VirtualKeyCode::D) => {
-                right_pressed = is_pressed(state);
+
+            Event::WindowEvent {
+                event: WindowEvent::Resized(size),
+                ..
+            } => {
+                if let Some(Err(e)) = renderer.as_mut().map(|r| r.resize(size.into())) {
+                    error!("Failed to resize: {e}");
+                    *control_flow = ControlFlow::Exit;
+                }
             }
-            None | Some(_) => {}
-        },
 
-        Event::MainEventsCleared => {
-            let now = time::Instant::now();
-            dt = (now - last_frame_time).as_secs_f32();
-            last_frame_time = now;
+            Event::MainEventsCleared => {
+                let now = time::Instant::now();
+                let dt = (now - last_frame_time).as_secs_f32();
+                last_frame_time = now;
 
-            let mut velocity: Vec3 = vec3(0f32, 0f32, 0f32);
+                let camera = match player.as_mut() {
+                    Some(player) => {
+                        let start = *playback_start.get_or_insert(now);
+                        match player.next() {
+                            Some(camera) => camera,
+                            None => {
+                                let elapsed = (now - start).as_secs_f32();
+                                info!(
+                                    "Played {} frames in {elapsed:.2}s, {:.1} FPS on average",
+                                    player.frame(),
+                                    player.frame() as f32 / elapsed
+                                );
+                                *control_flow = ControlFlow::Exit;
+                                return;
+                            }
+                        }
+                    }
+                    None => controller.update(dt),
+                };
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder.record(dt, &camera);
+                }
 
-            if forward_pressed {
-                velocity += vec3(0.0, 0.0, speed);
-            }
-            if backward_pressed {
-                velocity += vec3(0.0, 0.0, -speed);
-            }
-            if left_pressed {
-                velocity += vec3(-speed, 0.0, 0.0);
-            }
-            if right_pressed {
-                velocity += vec3(speed, 0.0, 0.0);
-            }
+                if let Some(renderer) = renderer.as_mut() {
+                    renderer.set_camera(&camera);
+                    match renderer.draw() {
+                        // The swapchain is recreated by the next draw
+                        Ok(()) | Err(WreckageError::SwapchainOutOfDate) => {}
+                        Err(e) => {
+                            error!("Failed to draw: {e}");
+                            *control_flow = ControlFlow::Exit;
+                        }
+                    }
+                }
+                fps_counter += 1;
 
-            renderer.rotation = vec3(-pitch, yaw, 0f32);
-            renderer.position += &(if velocity.magnitude_squared() != 0f32 {
-                rotate_vec3(
-                    &(velocity / velocity.magnitude()),
-                    yaw,
-                    &vec3(0f32, 1f32, 0f32),
-                )
-            } else {
-                vec3(0f32, 0f32, 0f32)
-            } * dt);
-
-            renderer.draw();
-            fps_counter += 1;
-
-            if now - frame_begin > time::Duration::new(1, 0) {
-                frame_begin = now;
-                debug!("FPS: {}", fps_counter);
-                fps_counter = 0;
+                if now - frame_begin > time::Duration::new(1, 0) {
+                    frame_begin = now;
+                    match renderer.as_ref().map(|r| r.capabilities()) {
+                        Some(caps) if caps.progressive => {
+                            debug!("FPS: {}, {} samples", fps_counter, caps.samples)
+                        }
+                        _ => debug!("FPS: {}", fps_counter),
+                    }
+                    fps_counter = 0;
+                }
+            }
+            Event::LoopDestroyed => {
+                if let (Some(recorder), Some(path)) = (&recorder, &record) {
+                    match recorder.path().save(path) {
+                        Ok(()) => info!("Saved camera path to {}", path.display()),
+                        Err(e) => error!("Failed to save the camera path: {e}"),
+                    }
+                }
             }
+            _ => (),
         }
-        _ => (),
     });
 }
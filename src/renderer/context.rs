@@ -0,0 +1,572 @@
+use std::sync::Arc;
+
+use crate::{
+    renderer::debug::{create_messenger, has_validation_layer, VALIDATION_LAYER},
+    Camera, DeviceReport, DeviceSelector, Light, Scene, WreckageError,
+};
+
+use image::RgbaImage;
+use log::{info, warn};
+use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
+use vulkano::device::physical::PhysicalDevice;
+use vulkano::device::DeviceOwned;
+use vulkano::device::{
+    DeviceCreateInfo, DeviceExtensions, Features, Queue, QueueCreateInfo, QueueFlags,
+};
+use vulkano::instance::debug::DebugUtilsMessenger;
+use vulkano::{
+    device::Device,
+    instance::{Instance, InstanceCreateInfo, InstanceExtensions},
+    memory::allocator::StandardMemoryAllocator,
+    Version, VulkanLibrary, VulkanObject,
+};
+
+/// The Vulkan instance and device shared by every renderer
+pub struct RenderingContext {
+    pub instance: Arc<Instance>,
+    pub memory_allocator: StandardMemoryAllocator,
+    pub command_buffer_allocator: StandardCommandBufferAllocator,
+    pub physical_device: Arc<PhysicalDevice>,
+    pub device: Arc<Device>,
+    pub queues: Vec<Arc<Queue>>,
+    /// Logs validation messages while the context is alive, only in debug mode
+    pub debug_messenger: Option<DebugUtilsMessenger>,
+}
+
+impl RenderingContext {
+    /// Picks the most capable device supporting `device_extensions`, see `builder` for
+    /// optional extensions and features
+    pub fn new(
+        vulkan_library: Arc<VulkanLibrary>,
+        instance_extensions: InstanceExtensions,
+        device_extensions: DeviceExtensions,
+    ) -> Result<Arc<Self>, WreckageError> {
+        Self::builder(vulkan_library)
+            .instance_extensions(instance_extensions)
+            .device_extensions(device_extensions)
+            .build()
+    }
+
+    pub fn builder(vulkan_library: Arc<VulkanLibrary>) -> RenderingContextBuilder {
+        RenderingContextBuilder {
+            vulkan_library,
+            instance_extensions: InstanceExtensions::empty(),
+            device_extensions: DeviceExtensions::empty(),
+            optional_device_extensions: DeviceExtensions::empty(),
+            features: Features::empty(),
+            optional_features: Features::empty(),
+            device: None,
+            debug: false,
+        }
+    }
+
+    /// Describes every device of the system, in the order `DeviceSelector::Index` counts them
+    pub fn devices(vulkan_library: Arc<VulkanLibrary>) -> Result<Vec<DeviceReport>, WreckageError> {
+        let instance = create_instance(vulkan_library, InstanceExtensions::empty(), vec![])?;
+        Ok(instance
+            .enumerate_physical_devices()?
+            .enumerate()
+            .map(|(index, device)| DeviceReport::new(index, &device))
+            .collect())
+    }
+
+    /// The extensions enabled on the device, the required ones along with the optional
+    /// ones it supports
+    pub fn enabled_extensions(&self) -> &DeviceExtensions {
+        self.device.enabled_extensions()
+    }
+
+    /// The features enabled on the device, the required ones along with the optional
+    /// ones it supports
+    pub fn enabled_features(&self) -> &Features {
+        self.device.enabled_features()
+    }
+
+    /// Names an object in validation messages and graphics debuggers, if debug utils are enabled
+    pub fn set_name<T: VulkanObject + DeviceOwned>(&self, object: &T, name: &str) {
+        if !self.instance.enabled_extensions().ext_debug_utils {
+            return;
+        }
+        if let Err(e) = self.device.set_debug_utils_object_name(object, Some(name)) {
+            warn!("Failed to name {name}: {e}");
+        }
+    }
+}
+
+/// Creates a `RenderingContext`, on a chosen device or on the most capable one
+pub struct RenderingContextBuilder {
+    vulkan_library: Arc<VulkanLibrary>,
+    instance_extensions: InstanceExtensions,
+    device_extensions: DeviceExtensions,
+    optional_device_extensions: DeviceExtensions,
+    features: Features,
+    optional_features: Features,
+    device: Option<DeviceSelector>,
+    debug: bool,
+}
+
+impl RenderingContextBuilder {
+    pub fn instance_extensions(mut self, extensions: InstanceExtensions) -> Self {
+        self.instance_extensions = extensions;
+        self
+    }
+
+    /// Devices not supporting all of them are skipped
+    pub fn device_extensions(mut self, extensions: DeviceExtensions) -> Self {
+        self.device_extensions = extensions;
+        self
+    }
+
+    /// Enabled if the device supports them, `RenderingContext::enabled_extensions` tells
+    /// which ones were
+    pub fn optional_device_extensions(mut self, extensions: DeviceExtensions) -> Self {
+        self.optional_device_extensions = extensions;
+        self
+    }
+
+    /// Devices not supporting all of them are skipped. Features brought by an extension
+    /// need it to be requested too.
+    pub fn features(mut self, features: Features) -> Self {
+        self.features = features;
+        self
+    }
+
+    /// Enabled if the device supports them, `RenderingContext::enabled_features` tells
+    /// which ones were
+    pub fn optional_features(mut self, features: Features) -> Self {
+        self.optional_features = features;
+        self
+    }
+
+    /// Only considers devices matching `selector`, the most capable of them is picked
+    pub fn device(mut self, selector: DeviceSelector) -> Self {
+        self.device = Some(selector);
+        self
+    }
+
+    /// Enables the validation layer if it's installed and logs its messages, which slows
+    /// down every Vulkan call
+    pub fn debug(mut self, enabled: bool) -> Self {
+        self.debug = enabled;
+        self
+    }
+
+    pub fn build(self) -> Result<Arc<RenderingContext>, WreckageError> {
+        let mut instance_extensions = self.instance_extensions;
+        let mut layers = vec![];
+        if self.debug {
+            if has_validation_layer(&self.vulkan_library)? {
+                layers.push(VALIDATION_LAYER.to_string());
+            } else {
+                warn!("{VALIDATION_LAYER} isn't installed, Vulkan calls won't be validated");
+            }
+
+            if self.vulkan_library.supported_extensions().ext_debug_utils {
+                instance_extensions.ext_debug_utils = true;
+            } else {
+                warn!("VK_EXT_debug_utils isn't supported, validation messages won't be logged");
+            }
+        }
+
+        let vulkan_instance = create_instance(self.vulkan_library, instance_extensions, layers)?;
+        let debug_messenger = match self.debug && instance_extensions.ext_debug_utils {
+            true => Some(create_messenger(vulkan_instance.clone())?),
+            false => None,
+        };
+
+        let mut physical_devices = vulkan_instance
+            .enumerate_physical_devices()?
+            .enumerate()
+            .collect::<Vec<_>>();
+
+        if let Some(selector) = &self.device {
+            physical_devices.retain(|(index, device)| {
+                let properties = device.properties();
+                selector.matches(*index, &properties.device_name, properties.device_uuid)
+            });
+            if physical_devices.is_empty() {
+                return Err(WreckageError::DeviceNotFound(selector.clone()));
+            }
+        }
+
+        let mut physical_devices = physical_devices
+            .into_iter()
+            .map(|(_, device)| device)
+            .filter(|p| {
+                p.supported_extensions().contains(&self.device_extensions)
+                    && p.supported_features().contains(&self.features)
+            })
+            .collect::<Vec<_>>();
+
+        physical_devices.sort_unstable_by_key(|device| match device.properties().device_type {
+            vulkano::device::physical::PhysicalDeviceType::DiscreteGpu => 0,
+            vulkano::device::physical::PhysicalDeviceType::IntegratedGpu => 1,
+            vulkano::device::physical::PhysicalDeviceType::VirtualGpu => 2,
+            vulkano::device::physical::PhysicalDeviceType::Cpu => 3,
+            vulkano::device::physical::PhysicalDeviceType::Other => 4,
+            _ => 5,
+        });
+
+        info!("Found {} devices:", physical_devices.len());
+        for device in &physical_devices {
+            info!(
+                "  {} ({:?})",
+                device.properties().device_name,
+                device.properties().device_type
+            );
+        }
+
+        let physical_device = physical_devices
+            .first()
+            .ok_or(WreckageError::NoSuitableDevice)?
+            .clone();
+        info!(
+            "Selected {} (driver v{})",
+            physical_device.properties().device_name,
+            physical_device
+                .properties()
+                .driver_info
+                .to_owned()
+                .unwrap_or("`undefined`".into())
+        );
+
+        let enabled_extensions = negotiate_extensions(
+            &self.device_extensions,
+            &self.optional_device_extensions,
+            physical_device.supported_extensions(),
+        );
+        // Core features need the version on both sides, the device's may be the higher one
+        let api_version = vulkan_instance
+            .api_version()
+            .min(physical_device.api_version());
+        let enabled_features = negotiate_features(
+            &self.features,
+            &self.optional_features,
+            physical_device.supported_features(),
+            api_version,
+            &enabled_extensions,
+        );
+        let missing = self
+            .optional_device_extensions
+            .difference(&enabled_extensions);
+        if missing != DeviceExtensions::empty() {
+            info!("Optional extensions not supported: {missing:?}");
+        }
+        let missing = self.optional_features.difference(&enabled_features);
+        if missing != Features::empty() {
+            info!("Optional features not supported: {missing:?}");
+        }
+
+        let queue_family_index = physical_device
+            .queue_family_properties()
+            .iter()
+            .enumerate()
+            .position(|(_queue_family_index, queue_family_properties)| {
+                queue_family_properties
+                    .queue_flags
+                    .contains(QueueFlags::GRAPHICS | QueueFlags::TRANSFER | QueueFlags::COMPUTE)
+            })
+            .ok_or(WreckageError::NoQueueFamily)? as u32;
+
+        let (device, queues) = Device::new(
+            physical_device.clone(),
+            DeviceCreateInfo {
+                enabled_extensions,
+                enabled_features,
+                queue_create_infos: vec![QueueCreateInfo {
+                    queue_family_index,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        )?;
+        let queues = queues.collect();
+
+        let allocator = StandardMemoryAllocator::new_default(device.clone());
+        let command_allocator =
+            StandardCommandBufferAllocator::new(device.clone(), Default::default());
+
+        Ok(Arc::new(RenderingContext {
+            instance: vulkan_instance,
+            memory_allocator: allocator,
+            command_buffer_allocator: command_allocator,
+            physical_device: physical_device.clone(),
+            device,
+            queues,
+            debug_messenger,
+        }))
+    }
+}
+
+// Everything required along with what's optional and supported. Devices are only
+// picked if they support everything required. Optional features of extensions are
+// also left out unless their extension or the Vulkan version it was promoted to is
+// enabled. The operators on `DeviceExtensions` are swapped in vulkano 0.33, so the
+// named methods are used.
+fn negotiate_extensions(
+    required: &DeviceExtensions,
+    optional: &DeviceExtensions,
+    supported: &DeviceExtensions,
+) -> DeviceExtensions {
+    required.union(&optional.intersection(supported))
+}
+
+fn negotiate_features(
+    required: &Features,
+    optional: &Features,
+    supported: &Features,
+    api_version: Version,
+    enabled_extensions: &DeviceExtensions,
+) -> Features {
+    let mut optional = optional.intersection(supported);
+    for (version, extension, features) in EXTENSION_FEATURES {
+        if api_version < *version
+            && extension.intersection(enabled_extensions) == DeviceExtensions::empty()
+        {
+            optional = optional.difference(features);
+        }
+    }
+    required.union(&optional)
+}
+
+macro_rules! provided_by {
+    ($version:ident, $extension:ident, [$($feature:ident),* $(,)?]) => {
+        (
+            Version::$version,
+            DeviceExtensions {
+                $extension: true,
+                ..DeviceExtensions::empty()
+            },
+            Features {
+                $($feature: true,)*
+                ..Features::empty()
+            },
+        )
+    };
+}
+
+// Features added by extensions, with the Vulkan version the extension was promoted to
+const EXTENSION_FEATURES: &[(Version, DeviceExtensions, Features)] = &[
+    provided_by!(
+        V1_1,
+        khr_16bit_storage,
+        [
+            storage_buffer16_bit_access,
+            uniform_and_storage_buffer16_bit_access,
+            storage_push_constant16,
+            storage_input_output16,
+        ]
+    ),
+    provided_by!(
+        V1_1,
+        khr_variable_pointers,
+        [variable_pointers, variable_pointers_storage_buffer,]
+    ),
+    provided_by!(
+        V1_2,
+        khr_8bit_storage,
+        [
+            storage_buffer8_bit_access,
+            uniform_and_storage_buffer8_bit_access,
+            storage_push_constant8,
+        ]
+    ),
+    provided_by!(V1_2, khr_shader_float16_int8, [shader_float16, shader_int8]),
+    provided_by!(
+        V1_2,
+        khr_shader_atomic_int64,
+        [shader_buffer_int64_atomics, shader_shared_int64_atomics,]
+    ),
+    provided_by!(
+        V1_2,
+        ext_descriptor_indexing,
+        [
+            runtime_descriptor_array,
+            descriptor_binding_partially_bound,
+            descriptor_binding_variable_descriptor_count,
+        ]
+    ),
+    provided_by!(V1_2, ext_scalar_block_layout, [scalar_block_layout]),
+    provided_by!(V1_2, ext_host_query_reset, [host_query_reset]),
+    provided_by!(V1_2, khr_timeline_semaphore, [timeline_semaphore]),
+    provided_by!(
+        V1_2,
+        khr_buffer_device_address,
+        [
+            buffer_device_address,
+            buffer_device_address_capture_replay,
+            buffer_device_address_multi_device,
+        ]
+    ),
+    provided_by!(V1_2, khr_vulkan_memory_model, [vulkan_memory_model]),
+    provided_by!(V1_3, khr_synchronization2, [synchronization2]),
+    provided_by!(V1_3, khr_dynamic_rendering, [dynamic_rendering]),
+    provided_by!(V1_3, khr_maintenance4, [maintenance4]),
+];
+
+fn create_instance(
+    vulkan_library: Arc<VulkanLibrary>,
+    enabled_extensions: InstanceExtensions,
+    enabled_layers: Vec<String>,
+) -> Result<Arc<Instance>, WreckageError> {
+    Ok(Instance::new(
+        vulkan_library,
+        InstanceCreateInfo {
+            application_name: Some("Wreckage".into()),
+            enabled_extensions,
+            enabled_layers,
+            ..Default::default()
+        },
+    )?)
+}
+
+/// What a renderer can do, reported so the app can adapt to the pipeline in use
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    pub name: &'static str,
+    /// Whether frames are presented to a surface, otherwise they can only be captured
+    pub presents: bool,
+    pub max_frames_in_flight: usize,
+    pub frames_in_flight: usize,
+    /// Whether frames are refined over time by accumulating samples
+    pub progressive: bool,
+    /// Samples per pixel accumulated since the last reset
+    pub samples: u32,
+}
+
+/// Settings of the progressive pipelines, others ignore them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenderSettings {
+    /// Samples traced per pixel every frame
+    pub samples_per_frame: u32,
+    /// Most bounces a path takes before it's cut off
+    pub max_depth: u32,
+    /// Accumulation stops after this many samples per pixel, 0 keeps refining
+    pub max_samples: u32,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            samples_per_frame: 1,
+            max_depth: 8,
+            max_samples: 0,
+        }
+    }
+}
+
+/// A pipeline drawing a scene, either to a surface or to frames read back on the CPU
+pub trait Renderer: Send {
+    fn context(&self) -> Arc<RenderingContext>;
+
+    fn capabilities(&self) -> Capabilities;
+
+    /// Renders a frame and presents it. Returns `WreckageError::SwapchainOutOfDate` if
+    /// the frame was skipped because the surface changed, drawing again recovers, and
+    /// `WreckageError::Headless` from renderers without a surface.
+    fn draw(&mut self) -> Result<(), WreckageError>;
+
+    /// Renders a frame and reads it back from the GPU
+    fn capture(&mut self) -> Result<RgbaImage, WreckageError>;
+
+    /// Changes the size of the frame, the swapchain is recreated before the next draw
+    fn resize(&mut self, size: [u32; 2]) -> Result<(), WreckageError>;
+
+    fn set_camera(&mut self, camera: &Camera);
+
+    /// Replaces the geometry drawn, the camera of the scene is left to `set_camera`
+    fn set_scene(&mut self, scene: &Scene) -> Result<(), WreckageError>;
+
+    /// Replaces the lights of the scene, cheaper than `set_scene` for animating them
+    fn set_lights(&mut self, lights: &[Light]) -> Result<(), WreckageError>;
+
+    /// Clamped to `Capabilities::max_frames_in_flight`
+    fn set_frames_in_flight(&mut self, count: usize) -> Result<(), WreckageError>;
+
+    fn set_settings(&mut self, _settings: &RenderSettings) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn optional_capabilities_are_enabled_where_supported() {
+        let required = DeviceExtensions {
+            khr_swapchain: true,
+            ..DeviceExtensions::empty()
+        };
+        let optional = DeviceExtensions {
+            khr_shader_float16_int8: true,
+            khr_buffer_device_address: true,
+            ..DeviceExtensions::empty()
+        };
+        let supported = DeviceExtensions {
+            khr_swapchain: true,
+            khr_buffer_device_address: true,
+            khr_maintenance1: true,
+            ..DeviceExtensions::empty()
+        };
+
+        let enabled = negotiate_extensions(&required, &optional, &supported);
+        assert!(enabled.khr_swapchain && enabled.khr_buffer_device_address);
+        assert!(!enabled.khr_shader_float16_int8 && !enabled.khr_maintenance1);
+
+        let features = Features {
+            shader_float16: true,
+            ..Features::empty()
+        };
+        let enabled = negotiate_features(
+            &Features::empty(),
+            &features,
+            &Features::empty(),
+            Version::V1_2,
+            &DeviceExtensions::empty(),
+        );
+        assert_eq!(enabled, Features::empty());
+    }
+
+    #[test]
+    fn optional_features_need_their_extension_or_version() {
+        let optional = Features {
+            shader_float16: true,
+            geometry_shader: true,
+            ..Features::empty()
+        };
+        let extension = DeviceExtensions {
+            khr_shader_float16_int8: true,
+            ..DeviceExtensions::empty()
+        };
+        let negotiate = |version, extensions: &DeviceExtensions| {
+            negotiate_features(
+                &Features::empty(),
+                &optional,
+                &optional,
+                version,
+                extensions,
+            )
+        };
+
+        let enabled = negotiate(Version::V1_1, &DeviceExtensions::empty());
+        assert!(!enabled.shader_float16 && enabled.geometry_shader);
+        assert_eq!(negotiate(Version::V1_1, &extension), optional);
+        assert_eq!(
+            negotiate(Version::V1_2, &DeviceExtensions::empty()),
+            optional
+        );
+
+        // Required features are left for device creation to reject
+        let required = Features {
+            shader_float16: true,
+            ..Features::empty()
+        };
+        let enabled = negotiate_features(
+            &required,
+            &Features::empty(),
+            &required,
+            Version::V1_0,
+            &DeviceExtensions::empty(),
+        );
+        assert_eq!(enabled, required);
+    }
+}
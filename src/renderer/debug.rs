@@ -0,0 +1,79 @@
+use std::sync::Arc;
+
+use log::{log, Level};
+use vulkano::{
+    instance::{
+        debug::{
+            DebugUtilsMessageSeverity, DebugUtilsMessageType, DebugUtilsMessenger,
+            DebugUtilsMessengerCreateInfo, Message,
+        },
+        Instance,
+    },
+    VulkanLibrary,
+};
+
+use crate::WreckageError;
+
+/// The layer checking every Vulkan call, shipped with the Vulkan SDK
+pub const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+/// Whether the validation layer is installed
+pub(crate) fn has_validation_layer(library: &VulkanLibrary) -> Result<bool, WreckageError> {
+    Ok(library
+        .layer_properties()?
+        .any(|layer| layer.name() == VALIDATION_LAYER))
+}
+
+/// Routes every message of the layers and the driver to `log`, until it's dropped
+pub(crate) fn create_messenger(
+    instance: Arc<Instance>,
+) -> Result<DebugUtilsMessenger, WreckageError> {
+    let create_info = DebugUtilsMessengerCreateInfo {
+        message_severity: DebugUtilsMessageSeverity::ERROR
+            | DebugUtilsMessageSeverity::WARNING
+            | DebugUtilsMessageSeverity::INFO
+            | DebugUtilsMessageSeverity::VERBOSE,
+        message_type: DebugUtilsMessageType::GENERAL
+            | DebugUtilsMessageType::VALIDATION
+            | DebugUtilsMessageType::PERFORMANCE,
+        ..DebugUtilsMessengerCreateInfo::user_callback(Arc::new(log_message))
+    };
+
+    // Safe as the callback never calls into Vulkan
+    Ok(unsafe { DebugUtilsMessenger::new(instance, create_info)? })
+}
+
+fn log_message(message: &Message) {
+    log!(
+        target: "vulkan",
+        log_level(message.severity),
+        "{}: {}",
+        message.layer_prefix.unwrap_or("Vulkan"),
+        message.description
+    );
+}
+
+fn log_level(severity: DebugUtilsMessageSeverity) -> Level {
+    if severity.intersects(DebugUtilsMessageSeverity::ERROR) {
+        Level::Error
+    } else if severity.intersects(DebugUtilsMessageSeverity::WARNING) {
+        Level::Warn
+    } else if severity.intersects(DebugUtilsMessageSeverity::INFO) {
+        Level::Info
+    } else {
+        Level::Trace
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn severities_map_to_log_levels() {
+        assert_eq!(log_level(DebugUtilsMessageSeverity::ERROR), Level::Error);
+        assert_eq!(log_level(DebugUtilsMessageSeverity::WARNING), Level::Warn);
+        assert_eq!(log_level(DebugUtilsMessageSeverity::INFO), Level::Info);
+        assert_eq!(log_level(DebugUtilsMessageSeverity::VERBOSE), Level::Trace);
+    }
+}
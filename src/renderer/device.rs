@@ -0,0 +1,200 @@
+use std::{fmt, str::FromStr, sync::Arc};
+
+use vulkano::device::physical::{PhysicalDevice, PhysicalDeviceType};
+
+/// Which device a `RenderingContext` is created on, instead of the most capable one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceSelector {
+    /// Position in the order the instance enumerates devices, as listed by `DeviceReport`
+    Index(usize),
+    /// The `device_uuid` reported by the driver, stable across reboots
+    Uuid([u8; 16]),
+    /// A case-insensitive part of the device name
+    Name(String),
+}
+
+impl DeviceSelector {
+    /// Whether the device at `index` named `name` is the one selected
+    pub fn matches(&self, index: usize, name: &str, uuid: Option<[u8; 16]>) -> bool {
+        match self {
+            Self::Index(i) => *i == index,
+            Self::Uuid(u) => uuid == Some(*u),
+            Self::Name(n) => name.to_lowercase().contains(&n.to_lowercase()),
+        }
+    }
+}
+
+/// Parses an index, a UUID in hex with or without dashes, or else a part of the name
+impl FromStr for DeviceSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("expected a device index, UUID or name".into());
+        }
+        if let Ok(index) = s.parse() {
+            return Ok(Self::Index(index));
+        }
+
+        let hex = s.replace('-', "");
+        if hex.len() == 32 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            let mut uuid = [0u8; 16];
+            for (i, byte) in uuid.iter_mut().enumerate() {
+                *byte =
+                    u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|e| e.to_string())?;
+            }
+            return Ok(Self::Uuid(uuid));
+        }
+
+        Ok(Self::Name(s.to_string()))
+    }
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Index(i) => write!(f, "device {i}"),
+            Self::Uuid(u) => write!(f, "UUID {}", format_uuid(u)),
+            Self::Name(n) => write!(f, "name \"{n}\""),
+        }
+    }
+}
+
+/// Formats a UUID the usual 8-4-4-4-12 way
+pub fn format_uuid(uuid: &[u8; 16]) -> String {
+    let hex = uuid.iter().map(|b| format!("{b:02x}")).collect::<String>();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// What a device is and the limits the compute pipelines run into
+#[derive(Debug, Clone)]
+pub struct DeviceReport {
+    pub index: usize,
+    pub name: String,
+    pub device_type: PhysicalDeviceType,
+    pub uuid: Option<[u8; 16]>,
+    pub driver: String,
+    pub api_version: String,
+    pub max_compute_work_group_size: [u32; 3],
+    pub max_compute_work_group_invocations: u32,
+    pub max_uniform_buffer_range: u32,
+    pub max_storage_buffer_range: u32,
+    pub max_push_constants_size: u32,
+    /// Names of the supported extensions, e.g. `VK_KHR_swapchain`
+    pub extensions: Vec<&'static str>,
+}
+
+impl DeviceReport {
+    pub fn new(index: usize, device: &Arc<PhysicalDevice>) -> Self {
+        let properties = device.properties();
+        let driver = match (&properties.driver_name, &properties.driver_info) {
+            (Some(name), Some(info)) => format!("{name} {info}"),
+            (Some(name), None) => name.clone(),
+            (None, Some(info)) => info.clone(),
+            (None, None) => format!("v{}", properties.driver_version),
+        };
+
+        Self {
+            index,
+            name: properties.device_name.clone(),
+            device_type: properties.device_type,
+            uuid: properties.device_uuid,
+            driver,
+            api_version: properties.api_version.to_string(),
+            max_compute_work_group_size: properties.max_compute_work_group_size,
+            max_compute_work_group_invocations: properties.max_compute_work_group_invocations,
+            max_uniform_buffer_range: properties.max_uniform_buffer_range,
+            max_storage_buffer_range: properties.max_storage_buffer_range,
+            max_push_constants_size: properties.max_push_constants_size,
+            extensions: device
+                .supported_extensions()
+                .into_iter()
+                .filter_map(|(name, supported)| supported.then_some(name))
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for DeviceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}: {} ({:?})", self.index, self.name, self.device_type)?;
+        if let Some(uuid) = &self.uuid {
+            writeln!(f, "    UUID: {}", format_uuid(uuid))?;
+        }
+        writeln!(
+            f,
+            "    Driver: {}, Vulkan {}",
+            self.driver, self.api_version
+        )?;
+        let [x, y, z] = self.max_compute_work_group_size;
+        writeln!(
+            f,
+            "    Max compute workgroup size: {x}x{y}x{z}, {} invocations",
+            self.max_compute_work_group_invocations
+        )?;
+        writeln!(
+            f,
+            "    Max uniform buffer range: {} bytes",
+            self.max_uniform_buffer_range
+        )?;
+        writeln!(
+            f,
+            "    Max storage buffer range: {} bytes",
+            self.max_storage_buffer_range
+        )?;
+        writeln!(
+            f,
+            "    Max push constants size: {} bytes",
+            self.max_push_constants_size
+        )?;
+        writeln!(f, "    Extensions ({}):", self.extensions.len())?;
+        for extension in &self.extensions {
+            writeln!(f, "        {extension}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selectors_are_parsed_by_shape() {
+        assert_eq!("1".parse(), Ok(DeviceSelector::Index(1)));
+        assert_eq!("RTX".parse(), Ok(DeviceSelector::Name("RTX".to_string())));
+
+        let uuid = [
+            0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab,
+            0xcd, 0xef,
+        ];
+        let formatted = format_uuid(&uuid);
+        assert_eq!(formatted, "01234567-89ab-cdef-0123-456789abcdef");
+        assert_eq!(formatted.parse(), Ok(DeviceSelector::Uuid(uuid)));
+        assert_eq!(
+            formatted.replace('-', "").to_uppercase().parse(),
+            Ok(DeviceSelector::Uuid(uuid))
+        );
+
+        assert!("  ".parse::<DeviceSelector>().is_err());
+    }
+
+    #[test]
+    fn names_match_ignoring_case() {
+        let selector = DeviceSelector::Name("geforce".to_string());
+        assert!(selector.matches(3, "NVIDIA GeForce RTX 3080", None));
+        assert!(!selector.matches(0, "AMD Radeon RX 6800", None));
+
+        assert!(DeviceSelector::Index(2).matches(2, "llvmpipe", None));
+        assert!(!DeviceSelector::Uuid([7; 16]).matches(0, "llvmpipe", None));
+        assert!(DeviceSelector::Uuid([7; 16]).matches(0, "llvmpipe", Some([7; 16])));
+    }
+}
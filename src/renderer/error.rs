@@ -0,0 +1,180 @@
+use std::{error::Error, fmt};
+
+use crate::DeviceSelector;
+
+use vulkano::{
+    buffer::BufferError,
+    command_buffer::{
+        BuildError, CommandBufferBeginError, CommandBufferExecError, CopyError,
+        PipelineExecutionError,
+    },
+    descriptor_set::{layout::DescriptorSetLayoutCreationError, DescriptorSetCreationError},
+    device::{physical::PhysicalDeviceError, DeviceCreationError},
+    image::{view::ImageViewCreationError, ImageError},
+    instance::{debug::DebugUtilsMessengerCreationError, InstanceCreationError},
+    pipeline::{compute::ComputePipelineCreationError, layout::PipelineLayoutCreationError},
+    shader::ShaderCreationError,
+    swapchain::{AcquireError, SwapchainCreationError},
+    sync::FlushError,
+    LoadingError, OomError, VulkanError,
+};
+
+/// Everything that can go wrong creating a `RenderingContext` or a renderer, or drawing with one
+#[derive(Debug)]
+pub enum WreckageError {
+    /// The Vulkan library couldn't be loaded, so only the `ReferenceRenderer` can be used
+    NoVulkanLibrary(LoadingError),
+    /// None of the devices support the extensions and features the renderer needs
+    NoSuitableDevice,
+    /// No device matches the `DeviceSelector` given to the `RenderingContextBuilder`
+    DeviceNotFound(DeviceSelector),
+    /// The device has no queue family for graphics, compute and transfers together
+    NoQueueFamily,
+    /// A compiled shader was rejected by the device
+    ShaderLoad(ShaderCreationError),
+    /// The surface changed and the frame was skipped, the swapchain is recreated on the next draw
+    SwapchainOutOfDate,
+    /// A headless renderer was asked to draw, it has nothing to present to and can only capture
+    Headless,
+    /// The device stopped responding, e.g. after a driver reset. Everything created from
+    /// the `RenderingContext` has to be created again.
+    DeviceLost,
+    /// Any other Vulkan call failing, most often from running out of memory
+    Vulkan(Box<dyn Error + Send + Sync>),
+}
+
+impl fmt::Display for WreckageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoVulkanLibrary(e) => write!(f, "no Vulkan library: {e}"),
+            Self::NoSuitableDevice => {
+                write!(f, "no device supports the required extensions and features")
+            }
+            Self::DeviceNotFound(selector) => write!(f, "no device matches {selector}"),
+            Self::NoQueueFamily => write!(
+                f,
+                "no queue family supports graphics, compute and transfers"
+            ),
+            Self::ShaderLoad(e) => write!(f, "failed to load shader: {e}"),
+            Self::SwapchainOutOfDate => write!(f, "swapchain is out of date"),
+            Self::Headless => write!(f, "headless renderers can't present, use capture"),
+            Self::DeviceLost => write!(f, "device lost"),
+            Self::Vulkan(e) => write!(f, "Vulkan error: {e}"),
+        }
+    }
+}
+
+impl Error for WreckageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::NoVulkanLibrary(e) => Some(e),
+            Self::ShaderLoad(e) => Some(e),
+            Self::Vulkan(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<LoadingError> for WreckageError {
+    fn from(e: LoadingError) -> Self {
+        Self::NoVulkanLibrary(e)
+    }
+}
+
+impl From<ShaderCreationError> for WreckageError {
+    fn from(e: ShaderCreationError) -> Self {
+        Self::ShaderLoad(e)
+    }
+}
+
+impl From<VulkanError> for WreckageError {
+    fn from(e: VulkanError) -> Self {
+        match e {
+            VulkanError::DeviceLost => Self::DeviceLost,
+            e => Self::Vulkan(e.into()),
+        }
+    }
+}
+
+impl From<FlushError> for WreckageError {
+    fn from(e: FlushError) -> Self {
+        match e {
+            FlushError::DeviceLost => Self::DeviceLost,
+            FlushError::OutOfDate => Self::SwapchainOutOfDate,
+            e => Self::Vulkan(e.into()),
+        }
+    }
+}
+
+impl From<AcquireError> for WreckageError {
+    fn from(e: AcquireError) -> Self {
+        match e {
+            AcquireError::DeviceLost => Self::DeviceLost,
+            AcquireError::OutOfDate => Self::SwapchainOutOfDate,
+            e => Self::Vulkan(e.into()),
+        }
+    }
+}
+
+impl From<SwapchainCreationError> for WreckageError {
+    fn from(e: SwapchainCreationError) -> Self {
+        match e {
+            SwapchainCreationError::DeviceLost => Self::DeviceLost,
+            e => Self::Vulkan(e.into()),
+        }
+    }
+}
+
+// Errors with nothing to tell apart, they all end up as `WreckageError::Vulkan`
+macro_rules! impl_from_vulkan_error {
+    ($($error:ty),* $(,)?) => {
+        $(
+            impl From<$error> for WreckageError {
+                fn from(e: $error) -> Self {
+                    Self::Vulkan(e.into())
+                }
+            }
+        )*
+    };
+}
+
+impl_from_vulkan_error!(
+    BufferError,
+    BuildError,
+    CommandBufferBeginError,
+    CommandBufferExecError,
+    ComputePipelineCreationError,
+    CopyError,
+    DebugUtilsMessengerCreationError,
+    DescriptorSetCreationError,
+    DescriptorSetLayoutCreationError,
+    DeviceCreationError,
+    ImageError,
+    ImageViewCreationError,
+    InstanceCreationError,
+    OomError,
+    PhysicalDeviceError,
+    PipelineExecutionError,
+    PipelineLayoutCreationError,
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lost_devices_are_told_apart() {
+        assert!(matches!(
+            WreckageError::from(FlushError::DeviceLost),
+            WreckageError::DeviceLost
+        ));
+        assert!(matches!(
+            WreckageError::from(AcquireError::OutOfDate),
+            WreckageError::SwapchainOutOfDate
+        ));
+        assert!(matches!(
+            WreckageError::from(FlushError::Timeout),
+            WreckageError::Vulkan(_)
+        ));
+    }
+}
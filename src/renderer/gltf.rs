@@ -0,0 +1,515 @@
+extern crate nalgebra_glm as glm;
+
+use std::{collections::HashMap, error::Error, path::Path, sync::Arc};
+
+use glm::{vec3, vec4, Mat3, Mat4, Vec3};
+use gltf::{khr_lights_punctual::Kind, material::AlphaMode, mesh::Mode};
+use log::{debug, warn};
+
+use crate::{euler_angles, Camera, Light, Material, Mesh, MeshInstance, Projection, Scene, Vertex};
+
+// Extensions whose contents are mapped onto the scene, any others are ignored
+const SUPPORTED_EXTENSIONS: [&str; 4] = [
+    "KHR_lights_punctual",
+    "KHR_materials_emissive_strength",
+    "KHR_materials_ior",
+    "KHR_materials_transmission",
+];
+
+// How far the axes of a transform may differ in length before its scale counts as non-uniform
+const SCALE_TOLERANCE: f32 = 1e-4;
+
+impl Scene {
+    /// Adds the meshes, materials and lights of a glTF 2.0 file (`.gltf` or `.glb`) to the
+    /// scene, and places the camera at the first glTF camera if there is one. Anything the
+    /// renderer can't show is skipped with a warning.
+    pub fn import_gltf(
+        &mut self,
+        path: impl AsRef<Path>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let path = path.as_ref();
+        let gltf::Gltf { document, blob } = gltf::Gltf::open(path)?;
+        let buffers = gltf::import_buffers(&document, path.parent(), blob)?;
+
+        for extension in document.extensions_used() {
+            if !SUPPORTED_EXTENSIONS.contains(&extension) {
+                warn!("glTF extension {extension} isn't supported, ignoring it");
+            }
+        }
+        if document.animations().next().is_some() {
+            warn!("glTF animations aren't supported, showing the scene at rest");
+        }
+
+        let first_material = self.materials.len() as u32;
+        let mut importer = Importer {
+            scene: self,
+            buffers: &buffers,
+            first_material,
+            default_material: None,
+            meshes: HashMap::new(),
+            found_camera: false,
+        };
+        importer
+            .scene
+            .materials
+            .extend(document.materials().map(|m| material(&m)));
+
+        let Some(gltf_scene) = document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+        else {
+            warn!("{} has no scenes", path.display());
+            return Ok(());
+        };
+        for node in gltf_scene.nodes() {
+            importer.add_node(&node, &Mat4::identity());
+        }
+        Ok(())
+    }
+}
+
+// State kept while walking the node tree
+struct Importer<'a> {
+    scene: &'a mut Scene,
+    buffers: &'a [gltf::buffer::Data],
+    // Index of the first glTF material in `Scene::materials`
+    first_material: u32,
+    // Index of the material used by primitives without one, added once needed
+    default_material: Option<u32>,
+    // Every glTF primitive is turned into a mesh once, keyed by mesh and primitive index
+    meshes: HashMap<(usize, usize), Option<Arc<Mesh>>>,
+    found_camera: bool,
+}
+
+impl Importer<'_> {
+    fn add_node(&mut self, node: &gltf::Node, parent: &Mat4) {
+        let transform = parent * Mat4::from(node.transform().matrix());
+
+        if let Some(mesh) = node.mesh() {
+            if node.skin().is_some() || node.weights().is_some() {
+                warn!(
+                    "Skins and morph targets aren't supported, showing mesh {} at rest",
+                    mesh.index()
+                );
+            }
+            for primitive in mesh.primitives() {
+                self.add_primitive(&mesh, &primitive, &transform);
+            }
+        }
+
+        if let Some(camera) = node.camera() {
+            if self.found_camera {
+                debug!(
+                    "Ignoring glTF camera {}, only the first one is used",
+                    camera.index()
+                );
+            } else {
+                self.found_camera = true;
+                self.scene.camera = self.camera(&camera, &transform);
+            }
+        }
+
+        if let Some(light) = node.light() {
+            self.scene.lights.push(light_at(&light, &transform));
+        }
+
+        for child in node.children() {
+            self.add_node(&child, &transform);
+        }
+    }
+
+    fn add_primitive(&mut self, mesh: &gltf::Mesh, primitive: &gltf::Primitive, transform: &Mat4) {
+        let key = (mesh.index(), primitive.index());
+        let buffers = self.buffers;
+        let Some(shared) = self
+            .meshes
+            .entry(key)
+            .or_insert_with(|| read_primitive(primitive, buffers).map(Arc::new))
+            .clone()
+        else {
+            return;
+        };
+
+        let material = match primitive.material().index() {
+            Some(index) => self.first_material + index as u32,
+            None => self.default_material(),
+        };
+
+        let instance = match decompose(transform) {
+            Some((position, rotation, scale)) => {
+                MeshInstance::new(shared).with_transform(position, rotation, scale)
+            }
+            // Shapes the instance transform can't express are baked into their own mesh
+            None => MeshInstance::new(Arc::new(bake(&shared, transform))),
+        };
+        self.scene.meshes.push(instance.with_material(material));
+    }
+
+    // glTF's default material is a rough white surface
+    fn default_material(&mut self) -> u32 {
+        *self.default_material.get_or_insert_with(|| {
+            self.scene.materials.push(Material::Diffuse {
+                albedo: Vec3::repeat(1.0),
+            });
+            self.scene.materials.len() as u32 - 1
+        })
+    }
+
+    fn camera(&self, camera: &gltf::Camera, transform: &Mat4) -> Camera {
+        let mut result = Camera::default();
+        match camera.projection() {
+            // The aspect ratio always follows the frame
+            gltf::camera::Projection::Perspective(perspective) => {
+                result.fov = perspective.yfov();
+                result.near = perspective.znear();
+                if let Some(far) = perspective.zfar() {
+                    result.far = far;
+                }
+            }
+            gltf::camera::Projection::Orthographic(orthographic) => {
+                result.projection = Projection::Orthographic {
+                    height: 2.0 * orthographic.ymag(),
+                };
+                result.near = orthographic.znear();
+                result.far = orthographic.zfar();
+            }
+        }
+
+        // glTF cameras look down -Z like ours, only the scale needs removing
+        let rotation = Mat3::from_columns(&[0, 1, 2].map(|i| {
+            let axis = transform.column(i).xyz();
+            axis.try_normalize(f32::EPSILON).unwrap_or(axis)
+        }));
+        Camera {
+            position: -transform.column(3).xyz(),
+            orientation: glm::mat3_to_quat(&rotation),
+            ..result
+        }
+    }
+}
+
+// Triangles of the primitive, or None if it has none the renderer can use
+fn read_primitive(primitive: &gltf::Primitive, buffers: &[gltf::buffer::Data]) -> Option<Mesh> {
+    if primitive.mode() != Mode::Triangles {
+        warn!(
+            "glTF primitives drawn as {:?} aren't supported, skipping one",
+            primitive.mode()
+        );
+        return None;
+    }
+    if primitive.morph_targets().next().is_some() {
+        warn!("Morph targets aren't supported, using the base shape");
+    }
+
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+    let Some(positions) = reader.read_positions() else {
+        warn!("glTF primitive without positions, skipping it");
+        return None;
+    };
+
+    let mut vertices = positions
+        .map(|position| Vertex {
+            position: position.into(),
+            normal: Vec3::zeros(),
+        })
+        .collect::<Vec<_>>();
+    let has_normals = match reader.read_normals() {
+        Some(normals) => {
+            for (vertex, normal) in vertices.iter_mut().zip(normals) {
+                vertex.normal = normal.into();
+            }
+            true
+        }
+        None => false,
+    };
+    let indices = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..vertices.len() as u32).collect(),
+    };
+
+    let mut mesh = match Mesh::new(vertices, indices) {
+        Ok(mesh) => mesh,
+        Err(e) => {
+            warn!("Broken glTF primitive, skipping it: {e}");
+            return None;
+        }
+    };
+    if mesh.triangle_count() == 0 {
+        warn!("glTF primitive without triangles, skipping it");
+        return None;
+    }
+    if !has_normals {
+        mesh.smooth_normals(0..mesh.indices.len());
+    }
+    Some(mesh)
+}
+
+// Splits the transform into a position, Euler angles and a uniform scale, if it has no other parts
+fn decompose(transform: &Mat4) -> Option<(Vec3, Vec3, f32)> {
+    let axes = [0, 1, 2].map(|i| transform.column(i).xyz());
+    let scale = axes[0].magnitude();
+    let uniform = axes
+        .iter()
+        .all(|axis| (axis.magnitude() - scale).abs() <= SCALE_TOLERANCE * scale);
+    let rotation = Mat3::from_columns(&axes) / scale;
+    // Mirroring and shearing would also show up as the axes not forming a rotation
+    let orthonormal = (rotation.transpose() * rotation - Mat3::identity())
+        .abs()
+        .max()
+        <= SCALE_TOLERANCE
+        && rotation.determinant() > 0.0;
+
+    (scale > 0.0 && uniform && orthonormal)
+        .then(|| (transform.column(3).xyz(), euler_angles(&rotation), scale))
+}
+
+// Copy of the mesh with the transform applied to its vertices
+fn bake(mesh: &Mesh, transform: &Mat4) -> Mesh {
+    let normal_transform = transform
+        .fixed_view::<3, 3>(0, 0)
+        .try_inverse()
+        .unwrap_or_else(Mat3::identity)
+        .transpose();
+
+    let vertices = mesh
+        .vertices
+        .iter()
+        .map(|vertex| {
+            let p = vertex.position;
+            let normal = normal_transform * vertex.normal;
+            Vertex {
+                position: (transform * vec4(p.x, p.y, p.z, 1.0)).xyz(),
+                normal: normal.try_normalize(f32::EPSILON).unwrap_or(normal),
+            }
+        })
+        .collect();
+    Mesh {
+        vertices,
+        indices: mesh.indices.clone(),
+    }
+}
+
+fn material(material: &gltf::Material) -> Material {
+    let name = material.name().unwrap_or("unnamed");
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, _] = pbr.base_color_factor();
+    let albedo = vec3(r, g, b);
+
+    if pbr.base_color_texture().is_some()
+        || pbr.metallic_roughness_texture().is_some()
+        || material.normal_texture().is_some()
+        || material.occlusion_texture().is_some()
+        || material.emissive_texture().is_some()
+    {
+        warn!("Textures aren't supported, material {name} uses its constant factors");
+    }
+    if material.alpha_mode() != AlphaMode::Opaque {
+        warn!("Transparency isn't supported, material {name} is drawn opaque");
+    }
+
+    let emissive = Vec3::from(material.emissive_factor());
+    if emissive.max() > 0.0 {
+        return Material::Emissive {
+            colour: emissive,
+            strength: material.emissive_strength().unwrap_or(1.0),
+        };
+    }
+    if material
+        .transmission()
+        .is_some_and(|t| t.transmission_factor() > 0.0)
+    {
+        return Material::Dielectric {
+            ior: material.ior().unwrap_or(1.5),
+        };
+    }
+    // Surfaces are either fully metal or not at all, whichever is closer
+    if pbr.metallic_factor() >= 0.5 {
+        Material::Metal {
+            albedo,
+            roughness: pbr.roughness_factor(),
+        }
+    } else {
+        Material::Diffuse { albedo }
+    }
+}
+
+fn light_at(light: &gltf::khr_lights_punctual::Light, transform: &Mat4) -> Light {
+    if light.range().is_some() {
+        warn!(
+            "Light ranges aren't supported, light {} reaches everywhere",
+            light.index()
+        );
+    }
+
+    let colour = Vec3::from(light.color());
+    let intensity = light.intensity();
+    let position = transform.column(3).xyz();
+    // Lights shine down -Z
+    let direction = (transform * vec4(0.0, 0.0, -1.0, 0.0)).xyz();
+
+    match light.kind() {
+        Kind::Directional => Light::Directional {
+            direction,
+            colour,
+            intensity,
+        },
+        Kind::Point => Light::Point {
+            position,
+            colour,
+            intensity,
+        },
+        Kind::Spot {
+            outer_cone_angle, ..
+        } => Light::Spot {
+            position,
+            direction,
+            angle: outer_cone_angle,
+            colour,
+            intensity,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    #[test]
+    fn non_uniform_scale_is_not_decomposed() {
+        let uniform = Mat4::new_translation(&vec3(1.0, 2.0, 3.0)) * Mat4::new_scaling(2.0);
+        let (position, rotation, scale) = decompose(&uniform).unwrap();
+        assert_eq!(
+            (position, rotation, scale),
+            (vec3(1.0, 2.0, 3.0), Vec3::zeros(), 2.0)
+        );
+
+        let stretched = Mat4::new_nonuniform_scaling(&vec3(1.0, 2.0, 1.0));
+        assert!(decompose(&stretched).is_none());
+    }
+
+    #[test]
+    fn gltf_nodes_become_instances() {
+        let dir = env::temp_dir().join("wreckage_gltf");
+        fs::create_dir_all(&dir).unwrap();
+
+        // One triangle, positions followed by the indices
+        let mut data = vec![];
+        for value in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            data.extend(value.to_le_bytes());
+        }
+        for index in [0u16, 1, 2] {
+            data.extend(index.to_le_bytes());
+        }
+        fs::write(dir.join("triangle.bin"), &data).unwrap();
+        fs::write(
+            dir.join("triangle.gltf"),
+            r#"{
+                "asset": {"version": "2.0"},
+                "extensionsUsed": ["KHR_lights_punctual"],
+                "extensions": {"KHR_lights_punctual": {"lights": [
+                    {"type": "point", "color": [1, 0.5, 0], "intensity": 20}
+                ]}},
+                "scene": 0,
+                "scenes": [{"nodes": [0, 1, 2, 3]}],
+                "nodes": [
+                    {"mesh": 0, "translation": [0, 0, -5], "scale": [2, 2, 2]},
+                    {"mesh": 0, "scale": [1, 3, 1]},
+                    {"camera": 0, "translation": [0, 1, 4]},
+                    {"extensions": {"KHR_lights_punctual": {"light": 0}}, "translation": [0, 3, 0]}
+                ],
+                "cameras": [{"type": "perspective", "perspective": {"yfov": 0.8, "znear": 0.1}}],
+                "materials": [{"pbrMetallicRoughness": {"baseColorFactor": [1, 0, 0, 1], "metallicFactor": 0}}],
+                "meshes": [{"primitives": [{"attributes": {"POSITION": 0}, "indices": 1, "material": 0}]}],
+                "buffers": [{"uri": "triangle.bin", "byteLength": 42}],
+                "bufferViews": [
+                    {"buffer": 0, "byteLength": 36},
+                    {"buffer": 0, "byteOffset": 36, "byteLength": 6}
+                ],
+                "accessors": [
+                    {"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                     "min": [0, 0, 0], "max": [1, 1, 0]},
+                    {"bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR"}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let scene = Scene::load(dir.join("triangle.gltf")).unwrap();
+        assert!(scene.spheres.is_empty());
+        assert_eq!(scene.camera.position, vec3(0.0, -1.0, -4.0));
+        assert_eq!((scene.camera.fov, scene.camera.near), (0.8, 0.1));
+        assert_eq!(
+            scene.materials,
+            [Material::Diffuse {
+                albedo: vec3(1.0, 0.0, 0.0)
+            }]
+        );
+        assert_eq!(
+            scene.lights,
+            [Light::Point {
+                position: vec3(0.0, 3.0, 0.0),
+                colour: vec3(1.0, 0.5, 0.0),
+                intensity: 20.0,
+            }]
+        );
+
+        // The first instance keeps the shared mesh, the stretched one gets its own
+        let [shared, stretched] = &scene.meshes[..] else {
+            panic!("expected two instances");
+        };
+        assert_eq!((shared.position, shared.scale), (vec3(0.0, 0.0, -5.0), 2.0));
+        assert_eq!(shared.material, Some(0));
+        assert_eq!(shared.mesh.vertices[0].normal, vec3(0.0, 0.0, 1.0));
+        assert_eq!(stretched.scale, 1.0);
+        assert_eq!(stretched.mesh.vertices[2].position, vec3(0.0, 3.0, 0.0));
+    }
+
+    #[test]
+    fn broken_primitives_are_skipped() {
+        let dir = env::temp_dir().join("wreckage_gltf_broken");
+        fs::create_dir_all(&dir).unwrap();
+
+        // One triangle, then indices reaching past its vertices
+        let mut data = vec![];
+        for value in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            data.extend(value.to_le_bytes());
+        }
+        for index in [0u16, 1, 7] {
+            data.extend(index.to_le_bytes());
+        }
+        fs::write(dir.join("broken.bin"), &data).unwrap();
+        fs::write(
+            dir.join("broken.gltf"),
+            r#"{
+                "asset": {"version": "2.0"},
+                "scene": 0,
+                "scenes": [{"nodes": [0, 1, 2]}],
+                "nodes": [{"mesh": 0}, {"mesh": 1}, {"mesh": 2}],
+                "meshes": [
+                    {"primitives": [{"attributes": {"POSITION": 0}, "indices": 2}]},
+                    {"primitives": [{"attributes": {"POSITION": 1}}]},
+                    {"primitives": [{"attributes": {"POSITION": 0}}]}
+                ],
+                "buffers": [{"uri": "broken.bin", "byteLength": 42}],
+                "bufferViews": [
+                    {"buffer": 0, "byteLength": 36},
+                    {"buffer": 0, "byteOffset": 36, "byteLength": 6}
+                ],
+                "accessors": [
+                    {"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                     "min": [0, 0, 0], "max": [1, 1, 0]},
+                    {"bufferView": 0, "componentType": 5126, "count": 2, "type": "VEC3",
+                     "min": [0, 0, 0], "max": [1, 0, 0]},
+                    {"bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR"}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        // Only the last mesh has a triangle to draw
+        let scene = Scene::load(dir.join("broken.gltf")).unwrap();
+        assert_eq!(scene.meshes.len(), 1);
+        assert_eq!(scene.meshes[0].mesh.triangle_count(), 1);
+    }
+}
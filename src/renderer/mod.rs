@@ -1,7 +1,21 @@
+mod context;
+mod debug;
+mod device;
+mod error;
+mod gltf;
 pub mod pipelines;
-pub mod renderer;
+pub mod scene;
+
+pub use context::*;
+pub use debug::VALIDATION_LAYER;
+pub use device::*;
+pub use error::*;
 
 pub mod prelude {
-    pub use crate::pipelines::*;
-    pub use crate::renderer::*;
+    pub use super::context::*;
+    pub use super::debug::VALIDATION_LAYER;
+    pub use super::device::*;
+    pub use super::error::*;
+    pub use super::pipelines::*;
+    pub use super::scene::*;
 }
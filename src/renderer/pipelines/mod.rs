@@ -1,2 +1,124 @@
 pub mod naive;
 pub use naive::*;
+pub mod pathtrace;
+pub use pathtrace::*;
+
+use std::sync::Arc;
+
+use vulkano::swapchain::Surface;
+
+use crate::{Renderer, RenderingContext, Scene, WreckageError};
+
+/// Where a pipeline draws its frames
+pub enum RenderTarget {
+    Surface(Arc<Surface>),
+    /// Frames of the given size that are only read back with `capture`
+    Headless([u32; 2]),
+}
+
+pub type PipelineConstructor =
+    fn(Arc<RenderingContext>, RenderTarget, &Scene) -> Result<Box<dyn Renderer>, WreckageError>;
+
+/// The pipelines the app can pick from by name, in the order they were registered
+pub struct PipelineRegistry {
+    pipelines: Vec<(&'static str, PipelineConstructor)>,
+}
+
+impl PipelineRegistry {
+    pub fn empty() -> Self {
+        Self { pipelines: vec![] }
+    }
+
+    /// Adds a pipeline, replacing any registered under the same name
+    pub fn register(&mut self, name: &'static str, constructor: PipelineConstructor) {
+        match self.pipelines.iter_mut().find(|(n, _)| *n == name) {
+            Some(entry) => entry.1 = constructor,
+            None => self.pipelines.push((name, constructor)),
+        }
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.pipelines.iter().map(|(name, _)| *name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.names().any(|n| n == name)
+    }
+
+    /// The pipeline registered after `name`, wrapping around to the first
+    pub fn next(&self, name: &str) -> Option<&'static str> {
+        let i = self.pipelines.iter().position(|(n, _)| *n == name)?;
+        Some(self.pipelines[(i + 1) % self.pipelines.len()].0)
+    }
+
+    /// Returns None if no pipeline is registered under `name`
+    pub fn create(
+        &self,
+        name: &str,
+        ctx: Arc<RenderingContext>,
+        target: RenderTarget,
+        scene: &Scene,
+    ) -> Option<Result<Box<dyn Renderer>, WreckageError>> {
+        let (_, constructor) = self.pipelines.iter().find(|(n, _)| *n == name)?;
+        Some(constructor(ctx, target, scene))
+    }
+}
+
+impl Default for PipelineRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register("naive", |ctx, target, scene| {
+            Ok(match target {
+                RenderTarget::Surface(surface) => {
+                    Box::new(NaiveRenderer::new(ctx, surface, scene)?)
+                }
+                RenderTarget::Headless(size) => {
+                    Box::new(NaiveRenderer::headless(ctx, size, scene)?)
+                }
+            })
+        });
+        registry.register("pathtrace", |ctx, target, scene| {
+            Ok(match target {
+                RenderTarget::Surface(surface) => {
+                    Box::new(PathTraceRenderer::new(ctx, surface, scene)?)
+                }
+                RenderTarget::Headless(size) => {
+                    Box::new(PathTraceRenderer::headless(ctx, size, scene)?)
+                }
+            })
+        });
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stub(
+        _: Arc<RenderingContext>,
+        _: RenderTarget,
+        _: &Scene,
+    ) -> Result<Box<dyn Renderer>, WreckageError> {
+        Err(WreckageError::NoSuitableDevice)
+    }
+
+    #[test]
+    fn next_wraps_around() {
+        let mut registry = PipelineRegistry::empty();
+        registry.register("a", stub);
+        registry.register("b", stub);
+
+        assert_eq!(registry.next("a"), Some("b"));
+        assert_eq!(registry.next("b"), Some("a"));
+        assert_eq!(registry.next("c"), None);
+    }
+
+    #[test]
+    fn register_replaces_same_name() {
+        let mut registry = PipelineRegistry::default();
+        registry.register("naive", stub);
+
+        assert_eq!(registry.names().collect::<Vec<_>>(), ["naive", "pathtrace"]);
+    }
+}
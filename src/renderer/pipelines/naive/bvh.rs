@@ -0,0 +1,368 @@
+extern crate nalgebra_glm as glm;
+use glm::Vec3;
+use vulkano::buffer::BufferContents;
+
+// Number of buckets the centroids are sorted into when looking for a split
+const BIN_COUNT: usize = 12;
+// Nodes with at most this many primitives may become leaves
+const MAX_LEAF_SIZE: usize = 4;
+// Entries of the traversal stacks, the `BVH_STACK_SIZE` define of `main.comp` and
+// `MESH_STACK_SIZE` of `primitives.glsl`. Deeper trees can't be traversed.
+pub(crate) const BVH_STACK_SIZE: usize = 64;
+pub(crate) const MESH_STACK_SIZE: usize = 32;
+
+/// A node of the flattened hierarchy. Interior nodes have a `count` of zero
+/// and their children are stored next to each other at `first` and `first + 1`,
+/// leaves cover the primitives `first..first + count` in `Bvh::indices` order.
+#[derive(Debug, Clone, Copy)]
+pub struct BvhNode {
+    pub min: Vec3,
+    pub max: Vec3,
+    pub first: u32,
+    pub count: u32,
+}
+
+impl BvhNode {
+    pub fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+
+    pub fn raw(&self) -> RawBvhNode {
+        RawBvhNode {
+            min: [self.min.x, self.min.y, self.min.z],
+            first: self.first,
+            max: [self.max.x, self.max.y, self.max.z],
+            count: self.count,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, BufferContents)]
+pub struct RawBvhNode {
+    pub min: [f32; 3],
+    pub first: u32,
+    pub max: [f32; 3],
+    pub count: u32,
+}
+
+/// Bounding volume hierarchy built with binned SAH over primitive bounds.
+/// The root is the first node, the tree is empty if there were no primitives.
+#[derive(Debug, Default)]
+pub struct Bvh {
+    pub nodes: Vec<BvhNode>,
+    /// Index of the original primitive for every slot the leaves refer to,
+    /// primitives are expected to be uploaded in this order
+    pub indices: Vec<u32>,
+}
+
+#[derive(Clone, Copy)]
+struct Bin {
+    min: Vec3,
+    max: Vec3,
+    count: usize,
+}
+
+impl Bin {
+    fn empty() -> Self {
+        Self {
+            min: Vec3::repeat(f32::INFINITY),
+            max: Vec3::repeat(f32::NEG_INFINITY),
+            count: 0,
+        }
+    }
+
+    fn grow(&mut self, min: &Vec3, max: &Vec3, count: usize) {
+        self.min = self.min.inf(min);
+        self.max = self.max.sup(max);
+        self.count += count;
+    }
+}
+
+fn surface_area(min: &Vec3, max: &Vec3) -> f32 {
+    let d = max - min;
+    if d.x < 0.0 {
+        return 0.0;
+    }
+    2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+}
+
+impl Bvh {
+    /// Builds the hierarchy from `(max, min)` pairs as returned by `Primitive::bounds`,
+    /// for traversal with a stack of `stack_size` entries. Nodes are split at the median
+    /// once the tree gets close to the depth the stack allows, so it stays within it.
+    pub fn build(bounds: &[(Vec3, Vec3)], stack_size: usize) -> Self {
+        if bounds.is_empty() {
+            return Self::default();
+        }
+
+        let centroids = bounds
+            .iter()
+            .map(|(max, min)| (max + min) * 0.5)
+            .collect::<Vec<_>>();
+        let mut indices = (0..bounds.len() as u32).collect::<Vec<_>>();
+        let mut nodes = vec![BvhNode {
+            min: Vec3::zeros(),
+            max: Vec3::zeros(),
+            first: 0,
+            count: bounds.len() as u32,
+        }];
+
+        let mut stack = vec![(0usize, 0usize)];
+        while let Some((node_i, depth)) = stack.pop() {
+            let first = nodes[node_i].first as usize;
+            let count = nodes[node_i].count as usize;
+            let slice = &mut indices[first..first + count];
+
+            let mut node_bounds = Bin::empty();
+            let mut centroid_bounds = Bin::empty();
+            for &i in slice.iter() {
+                let (max, min) = &bounds[i as usize];
+                node_bounds.grow(min, max, 1);
+                centroid_bounds.grow(&centroids[i as usize], &centroids[i as usize], 1);
+            }
+            nodes[node_i].min = node_bounds.min;
+            nodes[node_i].max = node_bounds.max;
+
+            if count <= 1 {
+                continue;
+            }
+
+            // Leaves may be `stack_size - 1` levels down. Once halving is all that still
+            // fits, SAH could leave a child too large to get there.
+            let halving_only = median_depth(count) + depth + 1 >= stack_size;
+
+            // Partition in place so every leaf covers a contiguous range
+            let mut mid = 0;
+            if let Some((axis, split, cost)) =
+                find_split(bounds, &centroids, slice, &centroid_bounds).filter(|_| !halving_only)
+            {
+                let leaf_cost = surface_area(&node_bounds.min, &node_bounds.max) * count as f32;
+                if cost >= leaf_cost && count <= MAX_LEAF_SIZE {
+                    continue;
+                }
+
+                for j in 0..slice.len() {
+                    if centroids[slice[j] as usize][axis] < split {
+                        slice.swap(j, mid);
+                        mid += 1;
+                    }
+                }
+            }
+            // Without a split or with everything on one side, halve the node to keep leaves small
+            if mid == 0 || mid == count {
+                if count <= MAX_LEAF_SIZE {
+                    continue;
+                }
+                mid = median_split(&centroids, slice, &centroid_bounds);
+            }
+
+            let left = nodes.len();
+            for (first, count) in [(first, mid), (first + mid, count - mid)] {
+                nodes.push(BvhNode {
+                    min: Vec3::zeros(),
+                    max: Vec3::zeros(),
+                    first: first as u32,
+                    count: count as u32,
+                });
+            }
+            nodes[node_i].first = left as u32;
+            nodes[node_i].count = 0;
+            stack.push((left, depth + 1));
+            stack.push((left + 1, depth + 1));
+        }
+
+        Self { nodes, indices }
+    }
+
+    pub fn raw(&self) -> Vec<RawBvhNode> {
+        self.nodes.iter().map(BvhNode::raw).collect()
+    }
+}
+
+// Levels below a node of `count` primitives when it's halved until the leaves are small enough
+fn median_depth(count: usize) -> usize {
+    count
+        .div_ceil(MAX_LEAF_SIZE)
+        .next_power_of_two()
+        .trailing_zeros() as usize
+}
+
+// Orders the slice along the widest axis of the centroids up to its middle, which it returns
+fn median_split(centroids: &[Vec3], slice: &mut [u32], centroid_bounds: &Bin) -> usize {
+    let axis = (centroid_bounds.max - centroid_bounds.min).imax();
+    let mid = slice.len() / 2;
+    slice.select_nth_unstable_by(mid, |&a, &b| {
+        centroids[a as usize][axis].total_cmp(&centroids[b as usize][axis])
+    });
+    mid
+}
+
+// Returns the axis, the centroid coordinate to split at and the SAH cost of the split
+fn find_split(
+    bounds: &[(Vec3, Vec3)],
+    centroids: &[Vec3],
+    slice: &[u32],
+    centroid_bounds: &Bin,
+) -> Option<(usize, f32, f32)> {
+    let mut best: Option<(usize, f32, f32)> = None;
+
+    let axes = centroid_bounds.min.iter().zip(centroid_bounds.max.iter());
+    for (axis, (&lo, &hi)) in axes.enumerate() {
+        let extent = hi - lo;
+        if extent <= f32::EPSILON {
+            continue;
+        }
+
+        let scale = BIN_COUNT as f32 / extent;
+        let mut bins = [Bin::empty(); BIN_COUNT];
+        for &i in slice {
+            let b = (((centroids[i as usize][axis] - lo) * scale) as usize).min(BIN_COUNT - 1);
+            let (max, min) = &bounds[i as usize];
+            bins[b].grow(min, max, 1);
+        }
+
+        // Costs of everything left of each bin boundary, then add the right side
+        let mut costs = [0f32; BIN_COUNT - 1];
+        let mut acc = Bin::empty();
+        for b in 0..BIN_COUNT - 1 {
+            acc.grow(&bins[b].min, &bins[b].max, bins[b].count);
+            costs[b] = surface_area(&acc.min, &acc.max) * acc.count as f32;
+        }
+        let mut acc = Bin::empty();
+        for b in (1..BIN_COUNT).rev() {
+            acc.grow(&bins[b].min, &bins[b].max, bins[b].count);
+            costs[b - 1] += surface_area(&acc.min, &acc.max) * acc.count as f32;
+        }
+
+        for (b, &cost) in costs.iter().enumerate() {
+            if best.is_none_or(|(_, _, best_cost)| cost < best_cost) {
+                best = Some((axis, lo + (b + 1) as f32 / scale, cost));
+            }
+        }
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Sphere;
+    use glm::vec3;
+
+    fn spheres() -> Vec<Sphere> {
+        let mut spheres = vec![];
+        for x in 0..7 {
+            for y in 0..5 {
+                for z in 0..3 {
+                    spheres.push(Sphere::new(
+                        vec3(x as f32 * 1.5, (y * y) as f32, z as f32 - x as f32),
+                        ((x + y + z) % 4 + 1) as f32 / 4.0,
+                    ));
+                }
+            }
+        }
+        spheres
+    }
+
+    fn build(spheres: &[Sphere]) -> Bvh {
+        let bounds = spheres.iter().map(Sphere::bounds).collect::<Vec<_>>();
+        Bvh::build(&bounds, BVH_STACK_SIZE)
+    }
+
+    fn contains(outer: &BvhNode, min: &Vec3, max: &Vec3) -> bool {
+        (0..3).all(|i| outer.min[i] <= min[i] && max[i] <= outer.max[i])
+    }
+
+    #[test]
+    fn every_sphere_in_exactly_one_leaf() {
+        let spheres = spheres();
+        let bvh = build(&spheres);
+
+        let mut seen = vec![0; spheres.len()];
+        for node in bvh.nodes.iter().filter(|n| n.is_leaf()) {
+            for slot in node.first..node.first + node.count {
+                seen[bvh.indices[slot as usize] as usize] += 1;
+            }
+        }
+
+        assert!(seen.iter().all(|&n| n == 1));
+    }
+
+    #[test]
+    fn node_bounds_contain_children() {
+        let spheres = spheres();
+        let bvh = build(&spheres);
+
+        for node in &bvh.nodes {
+            if node.is_leaf() {
+                for slot in node.first..node.first + node.count {
+                    let (max, min) = spheres[bvh.indices[slot as usize] as usize].bounds();
+                    assert!(contains(node, &min, &max));
+                }
+            } else {
+                for child in &bvh.nodes[node.first as usize..node.first as usize + 2] {
+                    assert!(contains(node, &child.min, &child.max));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn splits_large_scenes() {
+        let bvh = build(&spheres());
+        assert!(bvh.nodes.len() > 1);
+        assert!(bvh
+            .nodes
+            .iter()
+            .filter(|n| n.is_leaf())
+            .all(|n| n.count as usize <= MAX_LEAF_SIZE));
+    }
+
+    #[test]
+    fn identical_spheres_are_split_in_the_middle() {
+        let spheres = vec![Sphere::new(vec3(1.0, 2.0, 3.0), 0.5); 10];
+        let bvh = build(&spheres);
+
+        let leaves = bvh.nodes.iter().filter(|n| n.is_leaf());
+        assert!(leaves.clone().all(|n| n.count as usize <= MAX_LEAF_SIZE));
+        assert_eq!(leaves.map(|n| n.count).sum::<u32>(), 10);
+
+        let few = build(&spheres[..MAX_LEAF_SIZE]);
+        assert_eq!(few.nodes.len(), 1);
+    }
+
+    fn depth(bvh: &Bvh, node: usize) -> usize {
+        match bvh.nodes[node] {
+            n if n.is_leaf() => 0,
+            n => 1 + depth(bvh, n.first as usize).max(depth(bvh, n.first as usize + 1)),
+        }
+    }
+
+    #[test]
+    fn skewed_scenes_fit_the_stack() {
+        // Every sphere half again as far as the last, SAH peels them off one at a time
+        let spheres = (0..40)
+            .map(|i| Sphere::new(vec3(1.5f32.powi(i), 0.0, 0.0), 0.1))
+            .collect::<Vec<_>>();
+        let bounds = spheres.iter().map(Sphere::bounds).collect::<Vec<_>>();
+
+        for stack_size in [6, MESH_STACK_SIZE] {
+            let bvh = Bvh::build(&bounds, stack_size);
+            assert!(depth(&bvh, 0) < stack_size);
+            assert!(bvh
+                .nodes
+                .iter()
+                .filter(|n| n.is_leaf())
+                .all(|n| n.count as usize <= MAX_LEAF_SIZE));
+        }
+    }
+
+    #[test]
+    fn empty_scene_has_no_nodes() {
+        let bvh = Bvh::build(&[], BVH_STACK_SIZE);
+        assert!(bvh.nodes.is_empty());
+        assert!(bvh.indices.is_empty());
+    }
+}
@@ -1,9 +1,70 @@
-use nalgebra_glm::{Mat4, Vec3};
+use nalgebra_glm::{self as glm, vec3, Mat3, Mat4, Quat, Vec3};
+use serde::{Deserialize, Serialize};
 use vulkano::buffer::BufferContents;
 
+/// Vertical field of view in radians of cameras that don't set one, about 79 degrees
+pub const DEFAULT_FOV: f32 = 1.383_671_6;
+/// Distance beyond which cameras that don't set one stop tracing
+pub const DEFAULT_FAR: f32 = 1000.0;
+/// Distance cameras that don't set one are focused at
+pub const DEFAULT_FOCUS_DISTANCE: f32 = 10.0;
+
+/// How a camera spreads its rays over the frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Projection {
+    /// A pinhole camera seeing `Camera::fov` up the frame
+    #[default]
+    Perspective,
+    /// Parallel rays over a view `height` world units tall, for overview maps
+    Orthographic { height: f32 },
+    /// Equidistant fisheye seeing `Camera::fov` up the frame, up to the whole sphere
+    Fisheye,
+    /// The whole sphere around the camera, longitude across the frame and latitude up it.
+    /// Frames twice as wide as they're tall keep it undistorted.
+    Equirectangular,
+}
+
+// Values of `RawCamera::projection`, matching the `PROJECTION_*` defines of `camera.glsl`
+pub(crate) const PROJECTION_PERSPECTIVE: u32 = 0;
+pub(crate) const PROJECTION_ORTHOGRAPHIC: u32 = 1;
+pub(crate) const PROJECTION_FISHEYE: u32 = 2;
+pub(crate) const PROJECTION_EQUIRECTANGULAR: u32 = 3;
+
+/// Where the scene is viewed from, and how it's projected
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "CameraFile", into = "CameraFile")]
 pub struct Camera {
-    pub(crate) position: Vec3,
-    pub(crate) rotation: Vec3,
+    /// Negated world position of the camera
+    pub position: Vec3,
+    /// Rotation from the camera to the world, the camera looks down its -Z axis
+    pub orientation: Quat,
+    pub projection: Projection,
+    /// Vertical field of view in radians
+    pub fov: f32,
+    /// Distances rays are traced between, along the view axis for perspective and
+    /// orthographic cameras and from the camera for the others
+    pub near: f32,
+    pub far: f32,
+    /// Radius of the lens, zero for a pinhole that keeps everything sharp. Larger lenses
+    /// blur what's away from the focus distance more, sampling it takes many samples.
+    pub aperture: f32,
+    /// Distance that's in focus, measured like `near` and `far`. Has to be positive.
+    pub focus_distance: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            position: Vec3::zeros(),
+            orientation: Quat::identity(),
+            projection: Projection::Perspective,
+            fov: DEFAULT_FOV,
+            near: 0.0,
+            far: DEFAULT_FAR,
+            aperture: 0.0,
+            focus_distance: DEFAULT_FOCUS_DISTANCE,
+        }
+    }
 }
 
 #[derive(BufferContents)]
@@ -11,11 +72,46 @@ pub struct Camera {
 pub struct RawCamera {
     position: [f32; 4],
     rotation_mat: [[f32; 4]; 4],
+    fov: f32,
+    near: f32,
+    far: f32,
+    projection: u32,
+    ortho_height: f32,
+    aperture: f32,
+    focus_distance: f32,
 }
 
 impl Camera {
+    /// Turns the camera at its position to look at `target`, with `up` pointing up in the frame
+    pub fn looking_at(self, target: &Vec3, up: &Vec3) -> Self {
+        let eye = -self.position;
+        let forward = (target - eye)
+            .try_normalize(f32::EPSILON)
+            .unwrap_or(-Vec3::z());
+        // Looking along `up`, any other axis does
+        let right = forward
+            .cross(up)
+            .try_normalize(f32::EPSILON)
+            .unwrap_or_else(|| forward.cross(&Vec3::x()).normalize());
+        let rotation = Mat3::from_columns(&[right, right.cross(&forward), -forward]);
+        Self {
+            orientation: glm::mat3_to_quat(&rotation),
+            ..self
+        }
+    }
+
+    pub(crate) fn rotation_matrix(&self) -> Mat4 {
+        glm::quat_to_mat4(&self.orientation)
+    }
+
     pub fn raw(&self) -> RawCamera {
-        let mat = Mat4::from_euler_angles(self.rotation.x, self.rotation.y, self.rotation.z);
+        let mat = self.rotation_matrix();
+        let (projection, ortho_height) = match self.projection {
+            Projection::Perspective => (PROJECTION_PERSPECTIVE, 0.0),
+            Projection::Orthographic { height } => (PROJECTION_ORTHOGRAPHIC, height),
+            Projection::Fisheye => (PROJECTION_FISHEYE, 0.0),
+            Projection::Equirectangular => (PROJECTION_EQUIRECTANGULAR, 0.0),
+        };
         RawCamera {
             position: [self.position.x, self.position.y, self.position.z, 0.0],
             rotation_mat: [
@@ -24,6 +120,199 @@ impl Camera {
                 [mat.m13, mat.m23, mat.m33, mat.m43],
                 [mat.m14, mat.m24, mat.m34, mat.m44],
             ],
+            fov: self.fov,
+            near: self.near,
+            far: self.far,
+            projection,
+            ortho_height,
+            aperture: self.aperture,
+            focus_distance: self.focus_distance,
         }
     }
 }
+
+/// Euler angles of a rotation matrix, the inverse of `Mat4::from_euler_angles`
+pub(crate) fn euler_angles(rotation: &Mat3) -> Vec3 {
+    let sin_pitch = (-rotation.m31).clamp(-1.0, 1.0);
+    // Looking straight up or down, roll and yaw turn about the same axis
+    if sin_pitch.abs() > 1.0 - 1e-6 {
+        let yaw = (-rotation.m12).atan2(rotation.m22);
+        return vec3(0.0, sin_pitch.asin(), yaw);
+    }
+
+    vec3(
+        rotation.m32.atan2(rotation.m33),
+        sin_pitch.asin(),
+        rotation.m21.atan2(rotation.m11),
+    )
+}
+
+/// How a camera is written in scene files. The orientation can be left out for
+/// Euler angles in `rotation`, or for a `look_at` target that overrides both.
+#[derive(Serialize, Deserialize)]
+struct CameraFile {
+    position: Vec3,
+    /// Rotation quaternion stored as `[x, y, z, w]`
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "unwrapped")]
+    orientation: Option<Quat>,
+    /// Euler angles in radians
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "unwrapped")]
+    rotation: Option<Vec3>,
+    /// World position to look at, with +Y up
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "unwrapped")]
+    look_at: Option<Vec3>,
+    #[serde(default)]
+    projection: Projection,
+    #[serde(default = "default_fov")]
+    fov: f32,
+    #[serde(default)]
+    near: f32,
+    #[serde(default = "default_far")]
+    far: f32,
+    #[serde(default)]
+    aperture: f32,
+    #[serde(default = "default_focus_distance")]
+    focus_distance: f32,
+}
+
+// Optional fields written without `Some`, as they're left out when missing
+mod unwrapped {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer, T: Serialize>(
+        value: &Option<T>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        value
+            .as_ref()
+            .expect("missing values are skipped")
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+        deserializer: D,
+    ) -> Result<Option<T>, D::Error> {
+        T::deserialize(deserializer).map(Some)
+    }
+}
+
+fn default_fov() -> f32 {
+    DEFAULT_FOV
+}
+
+fn default_far() -> f32 {
+    DEFAULT_FAR
+}
+
+fn default_focus_distance() -> f32 {
+    DEFAULT_FOCUS_DISTANCE
+}
+
+impl TryFrom<CameraFile> for Camera {
+    type Error = String;
+
+    fn try_from(file: CameraFile) -> Result<Self, Self::Error> {
+        // The lens divides by it
+        if file.focus_distance.is_nan() || file.focus_distance <= 0.0 {
+            return Err(format!(
+                "focus_distance has to be positive, not {}",
+                file.focus_distance
+            ));
+        }
+
+        let orientation = match (file.orientation, file.rotation) {
+            (Some(orientation), _) => orientation.normalize(),
+            (None, Some(r)) => glm::to_quat(&Mat4::from_euler_angles(r.x, r.y, r.z)),
+            (None, None) => Quat::identity(),
+        };
+        let camera = Self {
+            position: file.position,
+            orientation,
+            projection: file.projection,
+            fov: file.fov,
+            near: file.near,
+            far: file.far,
+            aperture: file.aperture,
+            focus_distance: file.focus_distance,
+        };
+        Ok(match file.look_at {
+            Some(target) => camera.looking_at(&target, &Vec3::y()),
+            None => camera,
+        })
+    }
+}
+
+impl From<Camera> for CameraFile {
+    fn from(camera: Camera) -> Self {
+        Self {
+            position: camera.position,
+            orientation: Some(camera.orientation),
+            rotation: None,
+            look_at: None,
+            projection: camera.projection,
+            fov: camera.fov,
+            near: camera.near,
+            far: camera.far,
+            aperture: camera.aperture,
+            focus_distance: camera.focus_distance,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).magnitude() < 1e-5, "{a} != {b}");
+    }
+
+    #[test]
+    fn euler_angles_invert_the_rotation() {
+        let angles = vec3(0.3, -0.7, 2.0);
+        let rotation = Mat4::from_euler_angles(angles.x, angles.y, angles.z);
+
+        let recovered = euler_angles(&rotation.fixed_view::<3, 3>(0, 0).into());
+        assert!((recovered - angles).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn looking_at_points_the_view_axis_at_the_target() {
+        let camera = Camera {
+            position: vec3(-1.0, -2.0, -3.0),
+            ..Default::default()
+        }
+        .looking_at(&vec3(4.0, 0.0, -2.0), &Vec3::y());
+        let rotation = camera.rotation_matrix();
+        let axis = |v: Vec3| (rotation * v.push(0.0)).xyz();
+
+        assert_close(axis(-Vec3::z()), vec3(3.0, -2.0, -5.0).normalize());
+        // Level, the camera's X axis stays horizontal
+        assert!(axis(Vec3::x()).y.abs() < 1e-5);
+        assert!(axis(Vec3::y()).y > 0.0);
+    }
+
+    #[test]
+    fn scene_files_can_use_euler_angles_or_a_target() {
+        let euler: Camera =
+            ron::from_str("(position: [0.0, 0.0, 0.0], rotation: [0.3, -0.7, 2.0])").unwrap();
+        let expected = Mat4::from_euler_angles(0.3, -0.7, 2.0);
+        assert!((euler.rotation_matrix() - expected).abs().max() < 1e-5);
+        assert_eq!(euler.fov, DEFAULT_FOV);
+
+        let target: Camera = ron::from_str(
+            "(position: [0.0, 0.0, -5.0], look_at: [0.0, 0.0, 0.0], fov: 0.5, far: 20.0, \
+              projection: Orthographic(height: 4.0))",
+        )
+        .unwrap();
+        assert!((target.orientation.coords - Quat::identity().coords).magnitude() < 1e-5);
+        assert_eq!((target.fov, target.near, target.far), (0.5, 0.0, 20.0));
+        assert_eq!(target.projection, Projection::Orthographic { height: 4.0 });
+
+        let written: Camera = ron::from_str(&ron::to_string(&euler).unwrap()).unwrap();
+        assert!((written.orientation.coords - euler.orientation.coords).magnitude() < 1e-6);
+
+        let unfocused = ron::from_str::<Camera>("(position: [0.0, 0.0, 0.0], focus_distance: 0.0)");
+        assert!(unfocused.is_err());
+    }
+}
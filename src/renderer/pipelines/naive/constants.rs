@@ -9,13 +9,11 @@ pub struct RendererConstants {
     pub(crate) aspect_ratio: f32,
     pub(crate) width: u32,
     pub(crate) height: u32,
-    pub(crate) min_depth: f32,
-    pub(crate) max_depth: f32,
 }
 
 unsafe impl SpecializationConstants for RendererConstants {
     fn descriptors() -> &'static [SpecializationMapEntry] {
-        static DESCRIPTORS: [SpecializationMapEntry; 5] = [
+        static DESCRIPTORS: [SpecializationMapEntry; 3] = [
             // XXX: SAFETY CHECK THIS PLS TY; VERY UNSAFE
             SpecializationMapEntry {
                 constant_id: 0,
@@ -32,16 +30,6 @@ unsafe impl SpecializationConstants for RendererConstants {
                 offset: 8,
                 size: 4,
             },
-            SpecializationMapEntry {
-                constant_id: 3,
-                offset: 12,
-                size: 4,
-            },
-            SpecializationMapEntry {
-                constant_id: 4,
-                offset: 16,
-                size: 4,
-            },
         ];
 
         &DESCRIPTORS
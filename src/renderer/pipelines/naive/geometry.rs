@@ -0,0 +1,229 @@
+extern crate nalgebra_glm as glm;
+
+use std::sync::Arc;
+
+use glm::Vec3;
+use log::{debug, warn};
+
+use crate::{
+    Bvh, BvhNode, Mesh, Primitive, RawPrimitive, RawVertex, Scene, Sphere, BVH_STACK_SIZE,
+    MESH_STACK_SIZE,
+};
+
+/// Everything the scene is built from, flattened into the buffers the shaders read.
+/// Shared by the GPU pipelines and the `ReferenceRenderer`.
+pub(crate) struct Geometry {
+    /// Stored in the order of the leaves of `bvh`
+    pub(crate) primitives: Vec<RawPrimitive>,
+    pub(crate) bvh: Bvh,
+    /// Vertices of every mesh one after another
+    pub(crate) vertices: Vec<RawVertex>,
+    /// Three per triangle, indexing into all the vertices
+    pub(crate) indices: Vec<u32>,
+    /// Hierarchies of every mesh one after another. Interior nodes index into this
+    /// list and leaves into the triangles, so no offsets are needed when tracing.
+    pub(crate) mesh_nodes: Vec<BvhNode>,
+}
+
+impl Geometry {
+    pub(crate) fn build(scene: &Scene) -> Self {
+        let mut geometry = Self {
+            primitives: vec![],
+            bvh: Bvh::default(),
+            vertices: vec![],
+            indices: vec![],
+            mesh_nodes: vec![],
+        };
+
+        let mut primitives = vec![];
+        let mut bounds = vec![];
+        for mut primitive in scene.all_primitives() {
+            // Out of range materials would be read past the end of the buffer
+            let material = primitive.material_mut();
+            if let Some(index) = *material {
+                if index as usize >= scene.materials.len() {
+                    warn!("Material {index} doesn't exist, shading by the normal instead");
+                    *material = None;
+                }
+            }
+            primitives.push(primitive.raw());
+            bounds.push(primitive.bounds());
+        }
+
+        // Every mesh is only stored once, however many instances it has
+        let mut roots: Vec<(Arc<Mesh>, u32)> = vec![];
+        for instance in &scene.meshes {
+            if instance.mesh.triangle_count() == 0 {
+                warn!("Mesh {} has no triangles", instance.path.display());
+                continue;
+            }
+            if let Err(e) = instance.mesh.validate() {
+                warn!(
+                    "Mesh {} is broken, skipping it: {e}",
+                    instance.path.display()
+                );
+                continue;
+            }
+            if instance.scale.is_nan() || instance.scale <= 0.0 {
+                warn!(
+                    "Mesh {} has a scale of {}",
+                    instance.path.display(),
+                    instance.scale
+                );
+                continue;
+            }
+
+            let mut instance = instance.clone();
+            if instance
+                .material
+                .is_some_and(|m| m as usize >= scene.materials.len())
+            {
+                warn!("Material doesn't exist, shading the mesh by its normal instead");
+                instance.material = None;
+            }
+
+            let root = match roots
+                .iter()
+                .find(|(mesh, _)| Arc::ptr_eq(mesh, &instance.mesh))
+            {
+                Some((_, root)) => *root,
+                None => {
+                    let root = geometry.add_mesh(&instance.mesh);
+                    roots.push((instance.mesh.clone(), root));
+                    root
+                }
+            };
+            primitives.push(instance.raw(root));
+            bounds.push(instance.bounds());
+        }
+
+        // The shader reads the primitive count from the buffer length, which can't be zero
+        if primitives.is_empty() {
+            warn!("Scene has no primitives");
+            let padding = Primitive::Sphere(Sphere::new(Vec3::zeros(), 0.0));
+            primitives.push(padding.raw());
+            bounds.push(padding.bounds());
+        }
+
+        // Hierarchy over the primitives, which are stored in the order of its leaves
+        geometry.bvh = Bvh::build(&bounds, BVH_STACK_SIZE);
+        debug!(
+            "Built a BVH of {} nodes over {} primitives",
+            geometry.bvh.nodes.len(),
+            primitives.len()
+        );
+        geometry.primitives = geometry
+            .bvh
+            .indices
+            .iter()
+            .map(|&i| primitives[i as usize])
+            .collect();
+
+        // Like the primitives, the mesh buffers can't be empty
+        if geometry.vertices.is_empty() {
+            geometry.vertices.push(RawVertex::default());
+            geometry.indices.extend([0, 0, 0]);
+            geometry.mesh_nodes.push(BvhNode {
+                min: Vec3::zeros(),
+                max: Vec3::zeros(),
+                first: 0,
+                count: 0,
+            });
+        }
+
+        geometry
+    }
+
+    // Appends the triangles of the mesh along with a hierarchy over them, returns its root
+    fn add_mesh(&mut self, mesh: &Mesh) -> u32 {
+        let first_vertex = self.vertices.len() as u32;
+        let first_triangle = (self.indices.len() / 3) as u32;
+        let first_node = self.mesh_nodes.len() as u32;
+
+        let bounds = (0..mesh.triangle_count())
+            .map(|i| mesh.triangle_bounds(i))
+            .collect::<Vec<_>>();
+        let bvh = Bvh::build(&bounds, MESH_STACK_SIZE);
+        debug!(
+            "Built a BVH of {} nodes over {} triangles",
+            bvh.nodes.len(),
+            bounds.len()
+        );
+
+        self.vertices.extend(mesh.vertices.iter().map(|v| v.raw()));
+        for &triangle in &bvh.indices {
+            let corners = &mesh.indices[triangle as usize * 3..triangle as usize * 3 + 3];
+            self.indices
+                .extend(corners.iter().map(|&i| i + first_vertex));
+        }
+        self.mesh_nodes.extend(bvh.nodes.iter().map(|node| BvhNode {
+            first: node.first
+                + if node.is_leaf() {
+                    first_triangle
+                } else {
+                    first_node
+                },
+            ..*node
+        }));
+
+        first_node
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MeshInstance, Vertex};
+    use glm::vec3;
+
+    fn triangle() -> Arc<Mesh> {
+        let vertex = |x, y| Vertex {
+            position: vec3(x, y, 0.0),
+            normal: vec3(0.0, 0.0, 1.0),
+        };
+        Arc::new(
+            Mesh::new(
+                vec![vertex(0.0, 0.0), vertex(1.0, 0.0), vertex(0.0, 1.0)],
+                vec![0, 1, 2],
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn instances_share_their_mesh() {
+        let mesh = triangle();
+        let mut scene = Scene::grid();
+        scene.spheres.clear();
+        scene.meshes = vec![
+            MeshInstance::new(mesh.clone()),
+            MeshInstance::new(mesh.clone()).with_transform(vec3(2.0, 0.0, 0.0), Vec3::zeros(), 1.0),
+            MeshInstance::new(triangle()),
+        ];
+
+        let geometry = Geometry::build(&scene);
+        assert_eq!(geometry.primitives.len(), 3);
+        assert_eq!(geometry.vertices.len(), 6);
+        assert_eq!(geometry.indices, [0, 1, 2, 3, 4, 5]);
+
+        let mut roots = geometry
+            .primitives
+            .iter()
+            .map(|p| p.root)
+            .collect::<Vec<_>>();
+        roots.sort();
+        assert_eq!(roots, [0, 0, 1]);
+    }
+
+    #[test]
+    fn empty_scene_is_padded() {
+        let mut scene = Scene::grid();
+        scene.spheres.clear();
+
+        let geometry = Geometry::build(&scene);
+        assert_eq!(geometry.primitives.len(), 1);
+        assert_eq!(geometry.vertices.len(), 1);
+        assert_eq!(geometry.indices.len(), 3);
+        assert_eq!(geometry.mesh_nodes.len(), 1);
+    }
+}
@@ -0,0 +1,107 @@
+extern crate nalgebra_glm as glm;
+use glm::Vec3;
+use serde::{Deserialize, Serialize};
+use vulkano::buffer::BufferContents;
+
+/// A light without any size, so the shadows it casts are hard
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Light {
+    /// Shines in every direction, falling off with the square of the distance
+    Point {
+        position: Vec3,
+        colour: Vec3,
+        intensity: f32,
+    },
+    /// A point light limited to a cone of `angle` radians around its direction,
+    /// fading out over the outer tenth of the cone
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        angle: f32,
+        colour: Vec3,
+        intensity: f32,
+    },
+    /// Parallel light from infinitely far away, like the sun
+    Directional {
+        direction: Vec3,
+        colour: Vec3,
+        intensity: f32,
+    },
+}
+
+// Values of `RawLight::kind`, matching the `LIGHT_*` defines of the shaders
+pub(crate) const LIGHT_POINT: u32 = 0;
+pub(crate) const LIGHT_SPOT: u32 = 1;
+pub(crate) const LIGHT_DIRECTIONAL: u32 = 2;
+
+// Share of the spot cone the light fades out over
+pub(crate) const SPOT_FADE: f32 = 0.1;
+
+impl Light {
+    /// A light giving off nothing, the shaders skip it
+    pub(crate) fn none() -> Self {
+        Self::Point {
+            position: Vec3::zeros(),
+            colour: Vec3::zeros(),
+            intensity: 0.0,
+        }
+    }
+
+    pub fn raw(&self) -> RawLight {
+        let (kind, position, direction, angle, colour, intensity) = match *self {
+            Self::Point {
+                position,
+                colour,
+                intensity,
+            } => (LIGHT_POINT, position, Vec3::zeros(), 0.0, colour, intensity),
+            Self::Spot {
+                position,
+                direction,
+                angle,
+                colour,
+                intensity,
+            } => (LIGHT_SPOT, position, direction, angle, colour, intensity),
+            Self::Directional {
+                direction,
+                colour,
+                intensity,
+            } => (
+                LIGHT_DIRECTIONAL,
+                Vec3::zeros(),
+                direction,
+                0.0,
+                colour,
+                intensity,
+            ),
+        };
+        let direction = direction.try_normalize(f32::EPSILON).unwrap_or_default();
+        // The fade needs the inner cosine to be larger than the outer one
+        let angle = angle.clamp(1e-3, std::f32::consts::PI);
+
+        RawLight {
+            position: [position.x, position.y, position.z],
+            kind,
+            direction: [direction.x, direction.y, direction.z],
+            intensity,
+            colour: [colour.x, colour.y, colour.z],
+            cos_outer: angle.cos(),
+            cos_inner: (angle * (1.0 - SPOT_FADE)).cos(),
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, BufferContents)]
+pub struct RawLight {
+    pub position: [f32; 3],
+    pub kind: u32,
+    // Unit vector the light shines along
+    pub direction: [f32; 3],
+    pub intensity: f32,
+    pub colour: [f32; 3],
+    // Cosines of the spot cone's edge and of where it starts fading
+    pub cos_outer: f32,
+    pub cos_inner: f32,
+    pub _padding: [f32; 3],
+}
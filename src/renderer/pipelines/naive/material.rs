@@ -0,0 +1,64 @@
+extern crate nalgebra_glm as glm;
+use glm::Vec3;
+use serde::{Deserialize, Serialize};
+use vulkano::buffer::BufferContents;
+
+/// How light interacts with a surface, spheres refer to these by their index in
+/// `Scene::materials`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Material {
+    /// Scatters light evenly in every direction
+    Diffuse { albedo: Vec3 },
+    /// Reflects light, blurred more the higher the roughness in `0..=1`
+    Metal { albedo: Vec3, roughness: f32 },
+    /// Clear material like glass or water, refracting by its index of refraction
+    Dielectric { ior: f32 },
+    /// Gives off light of its own
+    Emissive { colour: Vec3, strength: f32 },
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self::Diffuse {
+            albedo: Vec3::repeat(0.5),
+        }
+    }
+}
+
+// Values of `RawMaterial::kind`, matching the `MATERIAL_*` defines of the shaders
+pub(crate) const MATERIAL_DIFFUSE: u32 = 0;
+pub(crate) const MATERIAL_METAL: u32 = 1;
+pub(crate) const MATERIAL_DIELECTRIC: u32 = 2;
+pub(crate) const MATERIAL_EMISSIVE: u32 = 3;
+
+impl Material {
+    pub fn raw(&self) -> RawMaterial {
+        let (kind, albedo, roughness, ior, strength) = match *self {
+            Self::Diffuse { albedo } => (MATERIAL_DIFFUSE, albedo, 1.0, 1.0, 0.0),
+            Self::Metal { albedo, roughness } => (MATERIAL_METAL, albedo, roughness, 1.0, 0.0),
+            Self::Dielectric { ior } => (MATERIAL_DIELECTRIC, Vec3::repeat(1.0), 0.0, ior, 0.0),
+            Self::Emissive { colour, strength } => (MATERIAL_EMISSIVE, colour, 1.0, 1.0, strength),
+        };
+
+        RawMaterial {
+            albedo: [albedo.x, albedo.y, albedo.z],
+            kind,
+            roughness: roughness.clamp(0.0, 1.0),
+            ior,
+            strength,
+            _padding: 0.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, BufferContents)]
+pub struct RawMaterial {
+    // The emitted colour for emissive materials
+    pub albedo: [f32; 3],
+    pub kind: u32,
+    pub roughness: f32,
+    pub ior: f32,
+    pub strength: f32,
+    pub _padding: f32,
+}
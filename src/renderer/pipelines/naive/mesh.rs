@@ -0,0 +1,287 @@
+extern crate nalgebra_glm as glm;
+
+use std::{error::Error, path::Path, path::PathBuf, sync::Arc};
+
+use glm::{vec4, Mat4, Vec3};
+use serde::{Deserialize, Serialize};
+use vulkano::buffer::BufferContents;
+
+use super::primitives::{RawPrimitive, PRIMITIVE_MESH};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Vertex {
+    pub position: Vec3,
+    pub normal: Vec3,
+}
+
+impl Vertex {
+    pub fn raw(&self) -> RawVertex {
+        RawVertex {
+            position: [self.position.x, self.position.y, self.position.z],
+            _padding: 0.0,
+            normal: [self.normal.x, self.normal.y, self.normal.z],
+            _padding2: 0.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, BufferContents)]
+pub struct RawVertex {
+    pub position: [f32; 3],
+    pub _padding: f32,
+    pub normal: [f32; 3],
+    pub _padding2: f32,
+}
+
+/// Triangles sharing a list of vertices, every three indices form one triangle
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Mesh {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
+impl Mesh {
+    /// Fails if an index is past the end of the vertices
+    pub fn new(
+        vertices: Vec<Vertex>,
+        indices: Vec<u32>,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mesh = Self { vertices, indices };
+        mesh.validate()?;
+        Ok(mesh)
+    }
+
+    /// Checks that every index refers to a vertex
+    pub fn validate(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match self
+            .indices
+            .iter()
+            .find(|&&i| i as usize >= self.vertices.len())
+        {
+            Some(i) => Err(format!(
+                "index {i} is out of range of the {} vertices",
+                self.vertices.len()
+            )
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Reads every object of a Wavefront OBJ file into one mesh. Faces are
+    /// triangulated, objects without normals get smooth ones computed.
+    pub fn load_obj(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let options = tobj::LoadOptions {
+            single_index: true,
+            triangulate: true,
+            ..Default::default()
+        };
+        // Materials are given by the scene, not the file
+        let (models, _) = tobj::load_obj(path.as_ref(), &options)?;
+
+        let mut mesh = Self::default();
+        for model in models {
+            let first_vertex = mesh.vertices.len();
+            let first_index = mesh.indices.len();
+            let positions = &model.mesh.positions;
+            let normals = &model.mesh.normals;
+            let has_normals = normals.len() == positions.len();
+
+            for (i, position) in positions.chunks_exact(3).enumerate() {
+                mesh.vertices.push(Vertex {
+                    position: Vec3::from_row_slice(position),
+                    normal: match has_normals {
+                        true => Vec3::from_row_slice(&normals[i * 3..i * 3 + 3]),
+                        false => Vec3::zeros(),
+                    },
+                });
+            }
+            mesh.indices
+                .extend(model.mesh.indices.iter().map(|&i| i + first_vertex as u32));
+
+            mesh.validate()?;
+            if !has_normals {
+                mesh.smooth_normals(first_index..mesh.indices.len());
+            }
+        }
+
+        Ok(mesh)
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    /// Corners of the `i`th triangle
+    pub fn triangle(&self, i: usize) -> [Vec3; 3] {
+        [0, 1, 2].map(|corner| self.vertices[self.indices[i * 3 + corner] as usize].position)
+    }
+
+    /// Returns the `(max, min)` corners of the bounding box of the `i`th triangle
+    pub fn triangle_bounds(&self, i: usize) -> (Vec3, Vec3) {
+        let [a, b, c] = self.triangle(i);
+        (a.sup(&b).sup(&c), a.inf(&b).inf(&c))
+    }
+
+    /// Returns the `(max, min)` corners of the bounding box
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        self.vertices.iter().fold(
+            (Vec3::repeat(f32::NEG_INFINITY), Vec3::repeat(f32::INFINITY)),
+            |(max, min), vertex| (max.sup(&vertex.position), min.inf(&vertex.position)),
+        )
+    }
+
+    // Sets the normals of the vertices used by the given indices to the average of the
+    // faces around them, weighted by area
+    pub(crate) fn smooth_normals(&mut self, indices: std::ops::Range<usize>) {
+        for triangle in self.indices[indices.clone()].chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| self.vertices[triangle[i] as usize].position);
+            let face = (b - a).cross(&(c - a));
+            for &i in triangle {
+                self.vertices[i as usize].normal += face;
+            }
+        }
+        for &i in &self.indices[indices] {
+            let normal = &mut self.vertices[i as usize].normal;
+            *normal = normal.try_normalize(f32::EPSILON).unwrap_or(*normal);
+        }
+    }
+}
+
+fn unit_scale() -> f32 {
+    1.0
+}
+
+/// A mesh placed in the scene, instances of the same mesh share its triangles
+/// and hierarchy on the GPU
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MeshInstance {
+    /// OBJ file the mesh is loaded from by `Scene::load`, relative to the scene file
+    #[serde(default)]
+    pub path: PathBuf,
+    #[serde(default)]
+    pub position: Vec3,
+    /// Euler angles in radians, like `Camera::rotation`
+    #[serde(default)]
+    pub rotation: Vec3,
+    /// Has to be positive
+    #[serde(default = "unit_scale")]
+    pub scale: f32,
+    #[serde(default)]
+    pub material: Option<u32>,
+    #[serde(skip)]
+    pub mesh: Arc<Mesh>,
+}
+
+impl MeshInstance {
+    pub fn new(mesh: Arc<Mesh>) -> Self {
+        Self {
+            path: PathBuf::new(),
+            position: Vec3::zeros(),
+            rotation: Vec3::zeros(),
+            scale: 1.0,
+            material: None,
+            mesh,
+        }
+    }
+
+    pub fn with_transform(self, position: Vec3, rotation: Vec3, scale: f32) -> Self {
+        Self {
+            position,
+            rotation,
+            scale,
+            ..self
+        }
+    }
+
+    pub fn with_material(self, material: u32) -> Self {
+        Self {
+            material: Some(material),
+            ..self
+        }
+    }
+
+    /// `root` is the first node of the mesh's hierarchy in the mesh node buffer
+    pub fn raw(&self, root: u32) -> RawPrimitive {
+        RawPrimitive {
+            root,
+            ..RawPrimitive::new(PRIMITIVE_MESH, self.material)
+                .a(self.position, self.scale)
+                .rotation(&self.rotation)
+        }
+    }
+
+    /// Returns the `(max, min)` corners of the bounding box, around the corners of
+    /// the mesh's own box once turned
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        let (local_max, local_min) = self.mesh.bounds();
+        let transform = Mat4::from_euler_angles(self.rotation.x, self.rotation.y, self.rotation.z);
+
+        let mut max = Vec3::repeat(f32::NEG_INFINITY);
+        let mut min = Vec3::repeat(f32::INFINITY);
+        for corner in 0..8 {
+            let pick = |axis: usize| match corner >> axis & 1 {
+                0 => local_min[axis],
+                _ => local_max[axis],
+            };
+            let local = vec4(pick(0), pick(1), pick(2), 0.0) * self.scale;
+            let world = (transform * local).xyz() + self.position;
+            max = max.sup(&world);
+            min = min.inf(&world);
+        }
+        (max, min)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glm::vec3;
+    use std::{env, fs};
+
+    #[test]
+    fn obj_without_normals_is_smoothed() {
+        let path = env::temp_dir().join("wreckage_quad.obj");
+        fs::write(&path, "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n").unwrap();
+
+        let mesh = Mesh::load_obj(&path).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.triangle_count(), 2);
+        for vertex in &mesh.vertices {
+            assert!((vertex.normal - vec3(0.0, 0.0, 1.0)).magnitude() < 1e-6);
+        }
+        assert_eq!(mesh.bounds(), (vec3(1.0, 1.0, 0.0), Vec3::zeros()));
+    }
+
+    #[test]
+    fn instance_bounds_follow_the_transform() {
+        let mesh = Mesh::new(
+            vec![
+                Vertex {
+                    position: vec3(0.0, 0.0, 0.0),
+                    normal: vec3(0.0, 0.0, 1.0),
+                },
+                Vertex {
+                    position: vec3(1.0, 0.0, 0.0),
+                    normal: vec3(0.0, 0.0, 1.0),
+                },
+                Vertex {
+                    position: vec3(0.0, 1.0, 0.0),
+                    normal: vec3(0.0, 0.0, 1.0),
+                },
+            ],
+            vec![0, 1, 2],
+        )
+        .unwrap();
+        let instance = MeshInstance::new(Arc::new(mesh)).with_transform(
+            vec3(5.0, 0.0, 0.0),
+            vec3(0.0, 0.0, std::f32::consts::FRAC_PI_2),
+            2.0,
+        );
+
+        // A quarter turn around Z takes +X to +Y
+        let (max, min) = instance.bounds();
+        assert!((max - vec3(5.0, 2.0, 0.0)).magnitude() < 1e-5);
+        assert!((min - vec3(3.0, 0.0, 0.0)).magnitude() < 1e-5);
+    }
+}
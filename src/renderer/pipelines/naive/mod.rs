@@ -1,10 +1,21 @@
 mod renderer;
 pub use renderer::*;
 mod shaders;
-pub use shaders::*;
+use shaders::*;
 mod primitives;
 pub use primitives::*;
-mod constants;
-pub use primitives::*;
+mod mesh;
+pub use mesh::*;
+mod geometry;
+pub(crate) use geometry::*;
 mod camera;
+pub(crate) mod constants;
 pub use camera::*;
+mod material;
+pub use material::*;
+mod light;
+pub use light::*;
+mod bvh;
+pub use bvh::*;
+mod reference;
+pub use reference::*;
@@ -1,25 +1,55 @@
 extern crate nalgebra_glm as glm;
-use glm::{vec3, Vec3};
+use glm::{vec3, Mat4, Vec3};
+use serde::{Deserialize, Serialize};
 use vulkano::buffer::BufferContents;
 
-#[derive(Debug)]
+/// Value of `RawPrimitive::material` for primitives shaded by their normal
+pub(crate) const NO_MATERIAL: u32 = u32::MAX;
+
+// Values of `RawPrimitive::kind`, same as the defines in `primitives.glsl`
+pub(crate) const PRIMITIVE_SPHERE: u32 = 0;
+pub(crate) const PRIMITIVE_PLANE: u32 = 1;
+pub(crate) const PRIMITIVE_BOX: u32 = 2;
+pub(crate) const PRIMITIVE_ORIENTED_BOX: u32 = 3;
+pub(crate) const PRIMITIVE_CYLINDER: u32 = 4;
+pub(crate) const PRIMITIVE_CAPSULE: u32 = 5;
+pub(crate) const PRIMITIVE_TORUS: u32 = 6;
+pub(crate) const PRIMITIVE_MESH: u32 = 7;
+
+/// How far planes reach from the origin in the hierarchy, they aren't hit beyond it
+pub const PLANE_EXTENT: f32 = 10000.0;
+
+/// A sphere of the scene
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Sphere {
     pub pos: glm::Vec3,
     pub radius: f32,
+    /// Index into `Scene::materials`, primitives without one are shaded by their normal
+    #[serde(default)]
+    pub material: Option<u32>,
 }
 
 impl Sphere {
     pub fn new(pos: glm::Vec3, radius: f32) -> Self {
-        Self { radius, pos }
+        Self {
+            radius,
+            pos,
+            material: None,
+        }
     }
 
-    pub fn raw(self) -> RawSphere {
-        RawSphere {
-            radius: self.radius,
-            pos: [self.pos.x, self.pos.y, self.pos.z],
+    pub fn with_material(self, material: u32) -> Self {
+        Self {
+            material: Some(material),
+            ..self
         }
     }
 
+    pub fn raw(&self) -> RawPrimitive {
+        RawPrimitive::new(PRIMITIVE_SPHERE, self.material).a(self.pos, self.radius)
+    }
+
+    /// Returns the `(max, min)` corners of the bounding box
     pub fn bounds(&self) -> (Vec3, Vec3) {
         (
             vec3(self.radius, self.radius, self.radius) + self.pos,
@@ -28,9 +58,445 @@ impl Sphere {
     }
 }
 
-#[repr(C)]
-#[derive(Debug, BufferContents)]
-pub struct RawSphere {
-    pub pos: [f32; 3],
+/// The points `p` with `dot(normal, p) == distance`, such as the ground
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Plane {
+    pub normal: Vec3,
+    pub distance: f32,
+    #[serde(default)]
+    pub material: Option<u32>,
+}
+
+impl Plane {
+    pub fn new(normal: Vec3, distance: f32) -> Self {
+        Self {
+            normal,
+            distance,
+            material: None,
+        }
+    }
+
+    pub fn with_material(self, material: u32) -> Self {
+        Self {
+            material: Some(material),
+            ..self
+        }
+    }
+
+    pub fn raw(&self) -> RawPrimitive {
+        let length = self.normal.magnitude();
+        RawPrimitive::new(PRIMITIVE_PLANE, self.material)
+            .a(self.normal / length, self.distance / length)
+    }
+
+    /// Returns the `(max, min)` corners of the bounding box. Planes are cut off at
+    /// `PLANE_EXTENT`, and only stay flat in the box if they face along an axis.
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        let normal = self.normal.normalize();
+        let mut max = Vec3::repeat(PLANE_EXTENT);
+        let mut min = Vec3::repeat(-PLANE_EXTENT);
+        if let Some(axis) = (0..3).find(|&i| normal[i].abs() > 1.0 - 1e-6) {
+            let offset = self.distance / self.normal.magnitude() * normal[axis].signum();
+            max[axis] = offset + 1e-4;
+            min[axis] = offset - 1e-4;
+        }
+        (max, min)
+    }
+}
+
+/// A box along the world axes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AaBox {
+    pub min: Vec3,
+    pub max: Vec3,
+    #[serde(default)]
+    pub material: Option<u32>,
+}
+
+impl AaBox {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self {
+            min,
+            max,
+            material: None,
+        }
+    }
+
+    pub fn with_material(self, material: u32) -> Self {
+        Self {
+            material: Some(material),
+            ..self
+        }
+    }
+
+    pub fn raw(&self) -> RawPrimitive {
+        RawPrimitive::new(PRIMITIVE_BOX, self.material)
+            .a(self.min, 0.0)
+            .b(self.max, 0.0)
+    }
+
+    /// Returns the `(max, min)` corners of the bounding box
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        (self.max, self.min)
+    }
+}
+
+/// A box turned by `rotation` around its center
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrientedBox {
+    pub center: Vec3,
+    /// Half the size of the box along each of its own axes
+    pub half_extents: Vec3,
+    /// Euler angles in radians, like `Camera::rotation`
+    #[serde(default)]
+    pub rotation: Vec3,
+    #[serde(default)]
+    pub material: Option<u32>,
+}
+
+impl OrientedBox {
+    pub fn new(center: Vec3, half_extents: Vec3, rotation: Vec3) -> Self {
+        Self {
+            center,
+            half_extents,
+            rotation,
+            material: None,
+        }
+    }
+
+    pub fn with_material(self, material: u32) -> Self {
+        Self {
+            material: Some(material),
+            ..self
+        }
+    }
+
+    pub fn raw(&self) -> RawPrimitive {
+        RawPrimitive::new(PRIMITIVE_ORIENTED_BOX, self.material)
+            .a(self.center, 0.0)
+            .b(self.half_extents, 0.0)
+            .rotation(&self.rotation)
+    }
+
+    /// Returns the `(max, min)` corners of the bounding box
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        let rotation = rotation_matrix(&self.rotation);
+        let extent = Vec3::from_fn(|i, _| {
+            (0..3)
+                .map(|j| rotation[(i, j)].abs() * self.half_extents[j])
+                .sum()
+        });
+        (self.center + extent, self.center - extent)
+    }
+}
+
+/// A cylinder closed by flat caps at `start` and `end`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cylinder {
+    pub start: Vec3,
+    pub end: Vec3,
+    pub radius: f32,
+    #[serde(default)]
+    pub material: Option<u32>,
+}
+
+impl Cylinder {
+    pub fn new(start: Vec3, end: Vec3, radius: f32) -> Self {
+        Self {
+            start,
+            end,
+            radius,
+            material: None,
+        }
+    }
+
+    pub fn with_material(self, material: u32) -> Self {
+        Self {
+            material: Some(material),
+            ..self
+        }
+    }
+
+    pub fn raw(&self) -> RawPrimitive {
+        RawPrimitive::new(PRIMITIVE_CYLINDER, self.material)
+            .a(self.start, self.radius)
+            .b(self.end, 0.0)
+    }
+
+    /// Returns the `(max, min)` corners of the bounding box
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        // The caps are discs, which reach less far along the axes the cylinder points in
+        let axis = (self.end - self.start).try_normalize(f32::EPSILON);
+        let extent = match axis {
+            Some(axis) => axis.map(|a| self.radius * (1.0 - a * a).max(0.0).sqrt()),
+            None => Vec3::repeat(self.radius),
+        };
+        (
+            self.start.sup(&self.end) + extent,
+            self.start.inf(&self.end) - extent,
+        )
+    }
+}
+
+/// A cylinder between `start` and `end` with rounded ends
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Capsule {
+    pub start: Vec3,
+    pub end: Vec3,
     pub radius: f32,
+    #[serde(default)]
+    pub material: Option<u32>,
+}
+
+impl Capsule {
+    pub fn new(start: Vec3, end: Vec3, radius: f32) -> Self {
+        Self {
+            start,
+            end,
+            radius,
+            material: None,
+        }
+    }
+
+    pub fn with_material(self, material: u32) -> Self {
+        Self {
+            material: Some(material),
+            ..self
+        }
+    }
+
+    pub fn raw(&self) -> RawPrimitive {
+        RawPrimitive::new(PRIMITIVE_CAPSULE, self.material)
+            .a(self.start, self.radius)
+            .b(self.end, 0.0)
+    }
+
+    /// Returns the `(max, min)` corners of the bounding box
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        let extent = Vec3::repeat(self.radius);
+        (
+            self.start.sup(&self.end) + extent,
+            self.start.inf(&self.end) - extent,
+        )
+    }
+}
+
+/// A ring around `center`, lying flat in the XZ plane until rotated
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Torus {
+    pub center: Vec3,
+    /// Distance from the center to the middle of the tube
+    pub major_radius: f32,
+    /// Radius of the tube
+    pub minor_radius: f32,
+    /// Euler angles in radians, like `Camera::rotation`
+    #[serde(default)]
+    pub rotation: Vec3,
+    #[serde(default)]
+    pub material: Option<u32>,
+}
+
+impl Torus {
+    pub fn new(center: Vec3, major_radius: f32, minor_radius: f32) -> Self {
+        Self {
+            center,
+            major_radius,
+            minor_radius,
+            rotation: Vec3::zeros(),
+            material: None,
+        }
+    }
+
+    pub fn with_rotation(self, rotation: Vec3) -> Self {
+        Self { rotation, ..self }
+    }
+
+    pub fn with_material(self, material: u32) -> Self {
+        Self {
+            material: Some(material),
+            ..self
+        }
+    }
+
+    pub fn raw(&self) -> RawPrimitive {
+        RawPrimitive::new(PRIMITIVE_TORUS, self.material)
+            .a(self.center, self.major_radius)
+            .b(Vec3::zeros(), self.minor_radius)
+            .rotation(&self.rotation)
+    }
+
+    /// Returns the `(max, min)` corners of the bounding box
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        let axis = (rotation_matrix(&self.rotation) * glm::vec4(0.0, 1.0, 0.0, 0.0)).xyz();
+        let extent =
+            axis.map(|a| self.major_radius * (1.0 - a * a).max(0.0).sqrt() + self.minor_radius);
+        (self.center + extent, self.center - extent)
+    }
+}
+
+/// Any of the shapes a scene can be built from, they all share the same buffer on the GPU
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Primitive {
+    Sphere(Sphere),
+    Plane(Plane),
+    AaBox(AaBox),
+    OrientedBox(OrientedBox),
+    Cylinder(Cylinder),
+    Capsule(Capsule),
+    Torus(Torus),
+}
+
+impl Primitive {
+    pub fn raw(&self) -> RawPrimitive {
+        match self {
+            Primitive::Sphere(p) => p.raw(),
+            Primitive::Plane(p) => p.raw(),
+            Primitive::AaBox(p) => p.raw(),
+            Primitive::OrientedBox(p) => p.raw(),
+            Primitive::Cylinder(p) => p.raw(),
+            Primitive::Capsule(p) => p.raw(),
+            Primitive::Torus(p) => p.raw(),
+        }
+    }
+
+    /// Returns the `(max, min)` corners of the bounding box
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        match self {
+            Primitive::Sphere(p) => p.bounds(),
+            Primitive::Plane(p) => p.bounds(),
+            Primitive::AaBox(p) => p.bounds(),
+            Primitive::OrientedBox(p) => p.bounds(),
+            Primitive::Cylinder(p) => p.bounds(),
+            Primitive::Capsule(p) => p.bounds(),
+            Primitive::Torus(p) => p.bounds(),
+        }
+    }
+
+    /// Index into `Scene::materials`, if the primitive has one
+    pub fn material(&self) -> Option<u32> {
+        match self {
+            Primitive::Sphere(p) => p.material,
+            Primitive::Plane(p) => p.material,
+            Primitive::AaBox(p) => p.material,
+            Primitive::OrientedBox(p) => p.material,
+            Primitive::Cylinder(p) => p.material,
+            Primitive::Capsule(p) => p.material,
+            Primitive::Torus(p) => p.material,
+        }
+    }
+
+    pub(crate) fn material_mut(&mut self) -> &mut Option<u32> {
+        match self {
+            Primitive::Sphere(p) => &mut p.material,
+            Primitive::Plane(p) => &mut p.material,
+            Primitive::AaBox(p) => &mut p.material,
+            Primitive::OrientedBox(p) => &mut p.material,
+            Primitive::Cylinder(p) => &mut p.material,
+            Primitive::Capsule(p) => &mut p.material,
+            Primitive::Torus(p) => &mut p.material,
+        }
+    }
+}
+
+macro_rules! impl_from_primitive {
+    ($($kind:ident),*) => {
+        $(impl From<$kind> for Primitive {
+            fn from(primitive: $kind) -> Self {
+                Primitive::$kind(primitive)
+            }
+        })*
+    };
+}
+
+impl_from_primitive!(Sphere, Plane, AaBox, OrientedBox, Cylinder, Capsule, Torus);
+
+fn rotation_matrix(rotation: &Vec3) -> Mat4 {
+    Mat4::from_euler_angles(rotation.x, rotation.y, rotation.z)
+}
+
+/// A primitive of any kind as the shaders read it, what `a`, `b` and `c` hold
+/// depends on `kind`, see `primitives.glsl`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, BufferContents)]
+pub struct RawPrimitive {
+    pub a: [f32; 4],
+    pub b: [f32; 4],
+    /// Rotation quaternion of oriented primitives, stored as `[x, y, z, w]`
+    pub c: [f32; 4],
+    pub kind: u32,
+    pub material: u32,
+    /// First node of the hierarchy of meshes, unused by other kinds
+    pub root: u32,
+    pub _padding: u32,
+}
+
+impl RawPrimitive {
+    pub(crate) fn new(kind: u32, material: Option<u32>) -> Self {
+        Self {
+            a: [0.0; 4],
+            b: [0.0; 4],
+            c: [0.0, 0.0, 0.0, 1.0],
+            kind,
+            material: material.unwrap_or(NO_MATERIAL),
+            root: 0,
+            _padding: 0,
+        }
+    }
+
+    pub(crate) fn a(self, v: Vec3, w: f32) -> Self {
+        Self {
+            a: [v.x, v.y, v.z, w],
+            ..self
+        }
+    }
+
+    pub(crate) fn b(self, v: Vec3, w: f32) -> Self {
+        Self {
+            b: [v.x, v.y, v.z, w],
+            ..self
+        }
+    }
+
+    pub(crate) fn rotation(self, rotation: &Vec3) -> Self {
+        let q = glm::to_quat(&rotation_matrix(rotation));
+        Self {
+            c: [q.i, q.j, q.k, q.w],
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ground_plane_bounds_are_flat() {
+        let (max, min) = Plane::new(vec3(0.0, 2.0, 0.0), -2.0).bounds();
+        assert!((max.y + 1.0).abs() < 1e-3 && (min.y + 1.0).abs() < 1e-3);
+        assert_eq!(max.x, PLANE_EXTENT);
+        assert_eq!(min.z, -PLANE_EXTENT);
+    }
+
+    #[test]
+    fn oriented_box_bounds_grow_when_turned() {
+        let quarter = std::f32::consts::FRAC_PI_4;
+        let straight = OrientedBox::new(Vec3::zeros(), vec3(1.0, 1.0, 1.0), Vec3::zeros());
+        let turned = OrientedBox::new(Vec3::zeros(), vec3(1.0, 1.0, 1.0), vec3(0.0, quarter, 0.0));
+
+        assert!((straight.bounds().0 - Vec3::repeat(1.0)).magnitude() < 1e-5);
+        let (max, _) = turned.bounds();
+        assert!((max.x - 2f32.sqrt()).abs() < 1e-5);
+        assert!((max.y - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn upright_cylinder_and_torus_bounds() {
+        let (max, min) = Cylinder::new(Vec3::zeros(), vec3(0.0, 2.0, 0.0), 0.5).bounds();
+        assert_eq!((max, min), (vec3(0.5, 2.0, 0.5), vec3(-0.5, 0.0, -0.5)));
+
+        let (max, min) = Torus::new(Vec3::zeros(), 1.0, 0.25).bounds();
+        assert!((max - vec3(1.25, 0.25, 1.25)).magnitude() < 1e-5);
+        assert!((min + vec3(1.25, 0.25, 1.25)).magnitude() < 1e-5);
+    }
 }
@@ -0,0 +1,1208 @@
+extern crate nalgebra_glm as glm;
+
+use std::f32::consts::PI;
+
+use glm::{vec3, vec4, Vec3};
+use image::{Rgba, RgbaImage};
+
+use crate::{
+    Camera, Geometry, Light, Material, Projection, RawLight, RawPrimitive, Scene, BVH_STACK_SIZE,
+    LIGHT_DIRECTIONAL, LIGHT_SPOT, MESH_STACK_SIZE, NO_MATERIAL, PRIMITIVE_BOX, PRIMITIVE_CAPSULE,
+    PRIMITIVE_CYLINDER, PRIMITIVE_MESH, PRIMITIVE_ORIENTED_BOX, PRIMITIVE_PLANE, PRIMITIVE_TORUS,
+};
+
+// Same values as the defines in `main.comp`
+const AMBIENT: f32 = 0.1;
+const SHADOW_BIAS: f32 = 0.001;
+const SHADOW_DISTANCE: f32 = 10000.0;
+// Same values as the defines in `primitives.glsl`
+const FAR_AWAY: f32 = 1e30;
+const TORUS_STEPS: usize = 128;
+const TORUS_EPSILON: f32 = 1e-4;
+
+struct Ray {
+    origin: Vec3,
+    direction: Vec3,
+}
+
+struct HitData {
+    point: Vec3,
+    colour: Vec3,
+    normal: Vec3,
+    distance: f32,
+    hit: bool,
+}
+
+/// A CPU port of `main.comp`, following the shader step by step so its output
+/// can be compared against the GPU. Works without Vulkan altogether.
+pub struct ReferenceRenderer {
+    size: [u32; 2],
+    // The same buffers the GPU pipelines upload
+    geometry: Geometry,
+    materials: Vec<Material>,
+    // Converted like the light buffer, so spot cones are computed the same way
+    lights: Vec<RawLight>,
+}
+
+impl ReferenceRenderer {
+    pub fn new(size: [u32; 2], scene: &Scene) -> Self {
+        Self {
+            size,
+            geometry: Geometry::build(scene),
+            materials: scene.materials.clone(),
+            lights: scene.lights.iter().map(Light::raw).collect(),
+        }
+    }
+
+    /// Draws a frame seen from `camera`, with the same orientation as the GPU
+    pub fn render(&self, camera: &Camera) -> RgbaImage {
+        let [width, height] = self.size;
+        let aspect_ratio = width as f32 / height as f32;
+        let rotation = camera.rotation_matrix();
+
+        let rotate = |v: Vec3| (rotation * vec4(v.x, v.y, v.z, 0.0)).xyz();
+
+        RgbaImage::from_fn(width, height, |x, y| {
+            let uv = (
+                1.0 - x as f32 / width as f32,
+                1.0 - y as f32 / height as f32,
+            );
+
+            // Outside the projection stays black
+            let Some(mut ray) = camera_ray(uv, aspect_ratio, camera) else {
+                return Rgba([0, 0, 0, u8::MAX]);
+            };
+            if camera.aperture > 0.0 {
+                let mut rng = y * width + x;
+                let u = (random(&mut rng), random(&mut rng));
+                thin_lens(&mut ray, u, camera.aperture, camera.focus_distance);
+            }
+            let ray = Ray {
+                origin: rotate(ray.origin) - camera.position,
+                direction: rotate(ray.direction),
+            };
+
+            let hit = self.raycast(&ray, camera.near, camera.far);
+            let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+            Rgba([
+                channel(hit.colour.x),
+                channel(hit.colour.y),
+                channel(hit.colour.z),
+                u8::MAX,
+            ])
+        })
+    }
+
+    fn raycast(&self, ray: &Ray, near: f32, far: f32) -> HitData {
+        let inv_direction = vec3(1.0, 1.0, 1.0).component_div(&ray.direction);
+        let mut hit_index = 0;
+        let mut active_hit = HitData::miss();
+
+        let mut stack = vec![0u32];
+        while let Some(node_i) = stack.pop() {
+            let node = &self.geometry.bvh.nodes[node_i as usize];
+            let closest = if active_hit.hit {
+                active_hit.distance
+            } else {
+                far
+            };
+            if !intersect_aabb(ray, &inv_direction, &node.min, &node.max, near, closest) {
+                continue;
+            }
+
+            if node.is_leaf() {
+                for i in node.first..node.first + node.count {
+                    let primitive = &self.geometry.primitives[i as usize];
+                    let new_hit = trace_primitive(&self.geometry, ray, primitive, near, far);
+                    if new_hit.hit && (!active_hit.hit || new_hit.distance < active_hit.distance) {
+                        active_hit = new_hit;
+                        hit_index = i as usize;
+                    }
+                }
+            } else if stack.len() + 2 <= BVH_STACK_SIZE {
+                stack.push(node.first + 1);
+                stack.push(node.first);
+            }
+        }
+
+        if !active_hit.hit {
+            return handle_miss(ray);
+        }
+
+        active_hit.colour = match self.geometry.primitives[hit_index].material {
+            NO_MATERIAL => self.lighting(&active_hit.colour, &active_hit, active_hit.colour),
+            material => self.shade(&self.materials[material as usize], &active_hit, ray),
+        };
+
+        active_hit
+    }
+
+    fn occluded(&self, ray: &Ray, max_distance: f32) -> bool {
+        let inv_direction = vec3(1.0, 1.0, 1.0).component_div(&ray.direction);
+
+        let mut stack = vec![0u32];
+        while let Some(node_i) = stack.pop() {
+            let node = &self.geometry.bvh.nodes[node_i as usize];
+            if !intersect_aabb(
+                ray,
+                &inv_direction,
+                &node.min,
+                &node.max,
+                SHADOW_BIAS,
+                max_distance,
+            ) {
+                continue;
+            }
+
+            if node.is_leaf() {
+                let primitives = &self.geometry.primitives
+                    [node.first as usize..(node.first + node.count) as usize];
+                if primitives.iter().any(|primitive| {
+                    trace_primitive(&self.geometry, ray, primitive, SHADOW_BIAS, max_distance).hit
+                }) {
+                    return true;
+                }
+            } else if stack.len() + 2 <= BVH_STACK_SIZE {
+                stack.push(node.first + 1);
+                stack.push(node.first);
+            }
+        }
+
+        false
+    }
+
+    // Returns None if the scene has no lights
+    fn direct_light(&self, point: &Vec3, normal: &Vec3, albedo: &Vec3) -> Option<Vec3> {
+        let mut lit = false;
+        let mut colour = Vec3::zeros();
+
+        for light in &self.lights {
+            if light.intensity <= 0.0 {
+                continue;
+            }
+            lit = true;
+
+            let direction = Vec3::from(light.direction);
+            let mut strength = light.intensity;
+            let (to_light, distance) = if light.kind == LIGHT_DIRECTIONAL {
+                (-direction, SHADOW_DISTANCE)
+            } else {
+                let to_light = Vec3::from(light.position) - point;
+                let distance = to_light.magnitude();
+                strength /= distance * distance;
+                if light.kind == LIGHT_SPOT {
+                    let cosine = (-to_light / distance).dot(&direction);
+                    strength *= smoothstep(light.cos_outer, light.cos_inner, cosine);
+                }
+                (to_light / distance, distance)
+            };
+
+            let cosine = normal.dot(&to_light);
+            if cosine <= 0.0 || strength <= 0.0 {
+                continue;
+            }
+
+            let shadow_ray = Ray {
+                origin: point + normal * SHADOW_BIAS,
+                direction: to_light,
+            };
+            if self.occluded(&shadow_ray, distance) {
+                continue;
+            }
+
+            colour += (albedo / PI).component_mul(&Vec3::from(light.colour)) * strength * cosine;
+        }
+
+        lit.then_some(colour)
+    }
+
+    fn lighting(&self, albedo: &Vec3, hit: &HitData, unlit: Vec3) -> Vec3 {
+        // The normal of the hit faces away from the camera
+        match self.direct_light(&hit.point, &-hit.normal, albedo) {
+            Some(direct) => AMBIENT * albedo + direct,
+            None => unlit,
+        }
+    }
+
+    fn shade(&self, material: &Material, hit: &HitData, ray: &Ray) -> Vec3 {
+        let dir = ray.direction.normalize();
+        let facing = hit.normal.dot(&dir).abs();
+
+        match *material {
+            Material::Diffuse { albedo } => {
+                self.lighting(&albedo, hit, albedo * (0.25 + 0.75 * facing))
+            }
+            Material::Metal { albedo, roughness } => {
+                let lit = self.lighting(&albedo, hit, albedo * (0.25 + 0.75 * facing));
+                let reflected = albedo.component_mul(&sky(&reflect(&dir, &hit.normal)));
+                glm::mix(&reflected, &lit, roughness.clamp(0.0, 1.0))
+            }
+            Material::Dielectric { ior } => {
+                let r0 = ((1.0 - ior) / (1.0 + ior)).powi(2);
+                let reflectance = r0 + (1.0 - r0) * (1.0 - facing).powi(5);
+                glm::mix(&sky(&dir), &sky(&reflect(&dir, &hit.normal)), reflectance)
+            }
+            Material::Emissive { colour, strength } => colour * strength,
+        }
+    }
+}
+
+impl HitData {
+    fn miss() -> Self {
+        Self {
+            point: Vec3::zeros(),
+            colour: Vec3::zeros(),
+            normal: Vec3::zeros(),
+            distance: 0.0,
+            hit: false,
+        }
+    }
+}
+
+fn trace_primitive(
+    geometry: &Geometry,
+    ray: &Ray,
+    primitive: &RawPrimitive,
+    t_min: f32,
+    t_max: f32,
+) -> HitData {
+    let Some((distance, outward_normal)) =
+        intersect_primitive(geometry, primitive, ray, t_min, t_max)
+    else {
+        return HitData::miss();
+    };
+
+    let mut normal = outward_normal;
+    let colour = 0.5 * (normal + vec3(1.0, 1.0, 1.0));
+
+    if ray.direction.dot(&normal) <= 0.0 {
+        normal = -normal;
+    }
+
+    HitData {
+        point: ray.origin + ray.direction * distance,
+        colour,
+        normal,
+        distance,
+        hit: true,
+    }
+}
+
+fn rotate(q: &[f32; 4], v: &Vec3) -> Vec3 {
+    let axis = vec3(q[0], q[1], q[2]);
+    v + 2.0 * axis.cross(&(axis.cross(v) + q[3] * v))
+}
+
+fn unrotate(q: &[f32; 4], v: &Vec3) -> Vec3 {
+    rotate(&[-q[0], -q[1], -q[2], q[3]], v)
+}
+
+fn xyz(v: &[f32; 4]) -> Vec3 {
+    vec3(v[0], v[1], v[2])
+}
+
+// Returns the root and whether it's the entry of the interval, which is preferred
+fn pick_root(enter: f32, exit: f32, t_min: f32, t_max: f32) -> Option<(f32, bool)> {
+    if (t_min..=t_max).contains(&enter) {
+        Some((enter, true))
+    } else if (t_min..=t_max).contains(&exit) {
+        Some((exit, false))
+    } else {
+        None
+    }
+}
+
+fn sphere_interval(ray: &Ray, center: &Vec3, radius: f32) -> Option<(f32, f32)> {
+    if radius <= 0.0 {
+        return None;
+    }
+
+    let oc = ray.origin - center;
+    let a = ray.direction.magnitude_squared();
+    let half_b = oc.dot(&ray.direction);
+    let c = oc.magnitude_squared() - radius * radius;
+    let discriminant = half_b * half_b - c * a;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let sqrtd = discriminant.sqrt();
+    Some(((-half_b - sqrtd) / a, (-half_b + sqrtd) / a))
+}
+
+// Returns the interval along with the normals of the faces it enters and exits through
+fn box_interval(ray: &Ray, lo: &Vec3, hi: &Vec3) -> Option<(f32, f32, Vec3, Vec3)> {
+    let inv_direction = vec3(1.0, 1.0, 1.0).component_div(&ray.direction);
+    let t0 = (lo - ray.origin).component_mul(&inv_direction);
+    let t1 = (hi - ray.origin).component_mul(&inv_direction);
+    let t_near = t0.inf(&t1);
+    let t_far = t0.sup(&t1);
+
+    let enter = t_near.x.max(t_near.y).max(t_near.z);
+    let exit = t_far.x.min(t_far.y).min(t_far.z);
+    if enter > exit {
+        return None;
+    }
+
+    // Only keep the first axis when the ray crosses an edge
+    let axis = |t: &Vec3, value: f32| {
+        let i = (0..3).find(|&i| t[i] == value).unwrap_or(2);
+        Vec3::from_fn(|j, _| if j == i { 1.0 } else { 0.0 })
+    };
+    let sign = ray.direction.map(glsl_sign);
+    Some((
+        enter,
+        exit,
+        -sign.component_mul(&axis(&t_near, enter)),
+        sign.component_mul(&axis(&t_far, exit)),
+    ))
+}
+
+fn tube_interval(ray: &Ray, start: &Vec3, end: &Vec3, radius: f32) -> Option<(f32, f32)> {
+    let axis = end - start;
+    let oc = ray.origin - start;
+    let axis_squared = axis.magnitude_squared();
+
+    let oc_perp = oc - axis * oc.dot(&axis) / axis_squared;
+    let dir_perp = ray.direction - axis * ray.direction.dot(&axis) / axis_squared;
+
+    let a = dir_perp.magnitude_squared();
+    let half_b = oc_perp.dot(&dir_perp);
+    let c = oc_perp.magnitude_squared() - radius * radius;
+    if a < 1e-12 {
+        return (c <= 0.0).then_some((-FAR_AWAY, FAR_AWAY));
+    }
+
+    let discriminant = half_b * half_b - c * a;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let sqrtd = discriminant.sqrt();
+    Some(((-half_b - sqrtd) / a, (-half_b + sqrtd) / a))
+}
+
+fn slab_interval(ray: &Ray, start: &Vec3, end: &Vec3) -> Option<(f32, f32)> {
+    let axis = end - start;
+    let along = ray.direction.dot(&axis);
+    let offset = (ray.origin - start).dot(&axis);
+    let axis_squared = axis.magnitude_squared();
+
+    if along.abs() < 1e-12 {
+        return (0.0..=axis_squared)
+            .contains(&offset)
+            .then_some((-FAR_AWAY, FAR_AWAY));
+    }
+
+    let t0 = -offset / along;
+    let t1 = (axis_squared - offset) / along;
+    Some((t0.min(t1), t0.max(t1)))
+}
+
+fn intersect_sphere(p: &RawPrimitive, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, Vec3)> {
+    let center = xyz(&p.a);
+    let (enter, exit) = sphere_interval(ray, &center, p.a[3])?;
+    let (t, _) = pick_root(enter, exit, t_min, t_max)?;
+    Some((t, (ray.origin + ray.direction * t - center) / p.a[3]))
+}
+
+fn intersect_plane(p: &RawPrimitive, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, Vec3)> {
+    let normal = xyz(&p.a);
+    let denominator = normal.dot(&ray.direction);
+    if denominator == 0.0 {
+        return None;
+    }
+    let t = (p.a[3] - normal.dot(&ray.origin)) / denominator;
+    (t_min..=t_max).contains(&t).then_some((t, normal))
+}
+
+fn intersect_box(p: &RawPrimitive, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, Vec3)> {
+    let (enter, exit, enter_normal, exit_normal) = box_interval(ray, &xyz(&p.a), &xyz(&p.b))?;
+    let (t, entering) = pick_root(enter, exit, t_min, t_max)?;
+    Some((t, if entering { enter_normal } else { exit_normal }))
+}
+
+fn intersect_oriented_box(
+    p: &RawPrimitive,
+    ray: &Ray,
+    t_min: f32,
+    t_max: f32,
+) -> Option<(f32, Vec3)> {
+    let local = Ray {
+        origin: unrotate(&p.c, &(ray.origin - xyz(&p.a))),
+        direction: unrotate(&p.c, &ray.direction),
+    };
+    let half_extents = xyz(&p.b);
+
+    let (enter, exit, enter_normal, exit_normal) =
+        box_interval(&local, &-half_extents, &half_extents)?;
+    let (t, entering) = pick_root(enter, exit, t_min, t_max)?;
+    let normal = if entering { enter_normal } else { exit_normal };
+    Some((t, rotate(&p.c, &normal)))
+}
+
+fn intersect_cylinder(p: &RawPrimitive, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, Vec3)> {
+    let start = xyz(&p.a);
+    let end = xyz(&p.b);
+    let (tube_enter, tube_exit) = tube_interval(ray, &start, &end, p.a[3])?;
+    let (slab_enter, slab_exit) = slab_interval(ray, &start, &end)?;
+
+    let enter = tube_enter.max(slab_enter);
+    let exit = tube_exit.min(slab_exit);
+    if enter > exit {
+        return None;
+    }
+    let (t, entering) = pick_root(enter, exit, t_min, t_max)?;
+
+    let axis = (end - start).normalize();
+    let on_cap = if entering {
+        slab_enter > tube_enter
+    } else {
+        slab_exit < tube_exit
+    };
+    let normal = if on_cap {
+        let along = glsl_sign(ray.direction.dot(&axis));
+        if entering {
+            -along * axis
+        } else {
+            along * axis
+        }
+    } else {
+        let offset = ray.origin + ray.direction * t - start;
+        (offset - axis * offset.dot(&axis)).normalize()
+    };
+    Some((t, normal))
+}
+
+fn intersect_capsule(p: &RawPrimitive, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, Vec3)> {
+    let start = xyz(&p.a);
+    let end = xyz(&p.b);
+    let radius = p.a[3];
+
+    // The capsule is convex, so the ray is inside it from the first entry into
+    // any of its parts until the last exit
+    let mut enter = FAR_AWAY;
+    let mut exit = -FAR_AWAY;
+    let mut grow = |(part_enter, part_exit): (f32, f32)| {
+        enter = enter.min(part_enter);
+        exit = exit.max(part_exit);
+    };
+    if let Some(part) = sphere_interval(ray, &start, radius) {
+        grow(part);
+    }
+    if let Some(part) = sphere_interval(ray, &end, radius) {
+        grow(part);
+    }
+    if let (Some(tube), Some(slab)) = (
+        tube_interval(ray, &start, &end, radius),
+        slab_interval(ray, &start, &end),
+    ) {
+        let body = (tube.0.max(slab.0), tube.1.min(slab.1));
+        if body.0 <= body.1 {
+            grow(body);
+        }
+    }
+
+    if enter > exit {
+        return None;
+    }
+    let (t, _) = pick_root(enter, exit, t_min, t_max)?;
+
+    let point = ray.origin + ray.direction * t;
+    let axis = end - start;
+    let h = ((point - start).dot(&axis) / axis.magnitude_squared().max(1e-12)).clamp(0.0, 1.0);
+    Some((t, (point - start - axis * h).normalize()))
+}
+
+fn torus_distance(point: &Vec3, major_radius: f32, minor_radius: f32) -> f32 {
+    let q = glm::vec2(point.xz().magnitude() - major_radius, point.y);
+    q.magnitude() - minor_radius
+}
+
+fn intersect_torus(p: &RawPrimitive, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, Vec3)> {
+    let major_radius = p.a[3];
+    let minor_radius = p.b[3];
+
+    let local = Ray {
+        origin: unrotate(&p.c, &(ray.origin - xyz(&p.a))),
+        direction: unrotate(&p.c, &ray.direction),
+    };
+
+    let (enter, exit) = sphere_interval(&local, &Vec3::zeros(), major_radius + minor_radius)?;
+
+    let scale = local.direction.magnitude();
+    let dir = local.direction / scale;
+    let mut s = t_min.max(enter) * scale;
+    let end = t_max.min(exit) * scale;
+    if s > end {
+        return None;
+    }
+
+    let start = local.origin + dir * s;
+    let side = if torus_distance(&start, major_radius, minor_radius) < 0.0 {
+        -1.0
+    } else {
+        1.0
+    };
+    for _ in 0..TORUS_STEPS {
+        let point = local.origin + dir * s;
+        let distance = side * torus_distance(&point, major_radius, minor_radius);
+        if distance < TORUS_EPSILON {
+            let ring = point.xz().normalize() * major_radius;
+            let normal = (point - vec3(ring.x, 0.0, ring.y)).normalize();
+            return Some((s / scale, rotate(&p.c, &normal)));
+        }
+        s += distance;
+        if s > end {
+            return None;
+        }
+    }
+
+    None
+}
+
+fn hits_bounds(
+    ray: &Ray,
+    inv_direction: &Vec3,
+    lo: &Vec3,
+    hi: &Vec3,
+    t_min: f32,
+    t_max: f32,
+) -> bool {
+    let t0 = (lo - ray.origin).component_mul(inv_direction);
+    let t1 = (hi - ray.origin).component_mul(inv_direction);
+    let t_near = t0.inf(&t1);
+    let t_far = t0.sup(&t1);
+
+    let enter = t_near.x.max(t_near.y).max(t_near.z.max(t_min));
+    let exit = t_far.x.min(t_far.y).min(t_far.z.min(t_max));
+    enter <= exit
+}
+
+// Möller–Trumbore, returns the distance and the barycentric weights of the second and third corners
+fn intersect_triangle(
+    ray: &Ray,
+    [p0, p1, p2]: [Vec3; 3],
+    t_min: f32,
+    t_max: f32,
+) -> Option<(f32, f32, f32)> {
+    let edge1 = p1 - p0;
+    let edge2 = p2 - p0;
+    let p = ray.direction.cross(&edge2);
+    let determinant = edge1.dot(&p);
+    if determinant == 0.0 {
+        return None;
+    }
+
+    let inv_determinant = 1.0 / determinant;
+    let s = ray.origin - p0;
+    let u = s.dot(&p) * inv_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = s.cross(&edge1);
+    let v = ray.direction.dot(&q) * inv_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = edge2.dot(&q) * inv_determinant;
+    (t_min..=t_max).contains(&t).then_some((t, u, v))
+}
+
+fn intersect_mesh(
+    geometry: &Geometry,
+    p: &RawPrimitive,
+    ray: &Ray,
+    t_min: f32,
+    t_max: f32,
+) -> Option<(f32, Vec3)> {
+    // Scaling the direction along with the origin keeps distances the same as in world space
+    let local = Ray {
+        origin: unrotate(&p.c, &(ray.origin - xyz(&p.a))) / p.a[3],
+        direction: unrotate(&p.c, &ray.direction) / p.a[3],
+    };
+    let inv_direction = vec3(1.0, 1.0, 1.0).component_div(&local.direction);
+    let vertex = |triangle: u32, corner: u32| {
+        &geometry.vertices[geometry.indices[(triangle * 3 + corner) as usize] as usize]
+    };
+    let corners = |triangle: u32| [0, 1, 2].map(|c| Vec3::from(vertex(triangle, c).position));
+
+    let mut hit = None;
+    let mut closest = t_max;
+
+    let mut stack = vec![p.root];
+    while let Some(node_i) = stack.pop() {
+        let node = &geometry.mesh_nodes[node_i as usize];
+        if !hits_bounds(&local, &inv_direction, &node.min, &node.max, t_min, closest) {
+            continue;
+        }
+
+        if node.is_leaf() {
+            for i in node.first..node.first + node.count {
+                if let Some((t, u, v)) = intersect_triangle(&local, corners(i), t_min, closest) {
+                    closest = t;
+                    hit = Some((i, u, v));
+                }
+            }
+        } else if stack.len() + 2 <= MESH_STACK_SIZE {
+            stack.push(node.first + 1);
+            stack.push(node.first);
+        }
+    }
+
+    let (triangle, u, v) = hit?;
+    let [n0, n1, n2] = [0, 1, 2].map(|c| Vec3::from(vertex(triangle, c).normal));
+    let mut normal = (1.0 - u - v) * n0 + u * n1 + v * n2;
+    if normal.dot(&normal) < 1e-12 {
+        let [p0, p1, p2] = corners(triangle);
+        normal = (p1 - p0).cross(&(p2 - p0));
+    }
+    Some((closest, rotate(&p.c, &normal.normalize())))
+}
+
+// Returns the distance and the normal facing out of the primitive, like `intersect_primitive`
+fn intersect_primitive(
+    geometry: &Geometry,
+    p: &RawPrimitive,
+    ray: &Ray,
+    t_min: f32,
+    t_max: f32,
+) -> Option<(f32, Vec3)> {
+    match p.kind {
+        PRIMITIVE_PLANE => intersect_plane(p, ray, t_min, t_max),
+        PRIMITIVE_BOX => intersect_box(p, ray, t_min, t_max),
+        PRIMITIVE_ORIENTED_BOX => intersect_oriented_box(p, ray, t_min, t_max),
+        PRIMITIVE_CYLINDER => intersect_cylinder(p, ray, t_min, t_max),
+        PRIMITIVE_CAPSULE => intersect_capsule(p, ray, t_min, t_max),
+        PRIMITIVE_TORUS => intersect_torus(p, ray, t_min, t_max),
+        PRIMITIVE_MESH => intersect_mesh(geometry, p, ray, t_min, t_max),
+        _ => intersect_sphere(p, ray, t_min, t_max),
+    }
+}
+
+fn sky(direction: &Vec3) -> Vec3 {
+    let dir = direction.normalize();
+    let t = 0.5 * (dir.y + 1.0);
+    (1.0 - t) * vec3(1.0, 1.0, 1.0) + t * vec3(0.5, 0.7, 1.0)
+}
+
+fn handle_miss(ray: &Ray) -> HitData {
+    HitData {
+        colour: sky(&ray.direction),
+        ..HitData::miss()
+    }
+}
+
+fn reflect(dir: &Vec3, normal: &Vec3) -> Vec3 {
+    dir - 2.0 * dir.dot(normal) * normal
+}
+
+// Unlike `f32::signum`, zero stays zero
+fn glsl_sign(x: f32) -> f32 {
+    if x == 0.0 {
+        0.0
+    } else {
+        x.signum()
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Same as `camera_ray` in `camera.glsl`, in the camera's space
+fn camera_ray(uv: (f32, f32), aspect_ratio: f32, camera: &Camera) -> Option<Ray> {
+    let ndc = (uv.0 * 2.0 - 1.0, uv.1 * 2.0 - 1.0);
+    let forward = vec3(0.0, 0.0, -1.0);
+
+    let (origin, direction) = match camera.projection {
+        Projection::Perspective => {
+            let tan_half_fov = (camera.fov / 2.0).tan();
+            let direction = vec3(
+                ndc.0 * aspect_ratio * tan_half_fov,
+                ndc.1 * tan_half_fov,
+                -1.0,
+            );
+            (Vec3::zeros(), direction)
+        }
+        Projection::Orthographic { height } => {
+            let origin = vec3(ndc.0 * aspect_ratio, ndc.1, 0.0) * height / 2.0;
+            (origin, forward)
+        }
+        Projection::Fisheye => {
+            let p = glm::vec2(ndc.0 * aspect_ratio, ndc.1);
+            let r = p.magnitude();
+            let theta = r * camera.fov / 2.0;
+            if theta > PI {
+                return None;
+            }
+            let side = if r > 0.0 {
+                p / r * theta.sin()
+            } else {
+                glm::Vec2::zeros()
+            };
+            (Vec3::zeros(), vec3(side.x, side.y, -theta.cos()))
+        }
+        Projection::Equirectangular => {
+            let longitude = ndc.0 * PI;
+            let latitude = ndc.1 * PI / 2.0;
+            let direction = vec3(
+                longitude.sin() * latitude.cos(),
+                latitude.sin(),
+                -longitude.cos() * latitude.cos(),
+            );
+            (Vec3::zeros(), direction)
+        }
+    };
+    Some(Ray { origin, direction })
+}
+
+// Same as `thin_lens` in `camera.glsl`
+fn thin_lens(ray: &mut Ray, u: (f32, f32), aperture: f32, focus_distance: f32) {
+    let r = aperture * u.0.sqrt();
+    let a = 2.0 * PI * u.1;
+    let lens = ray.origin + vec3(r * a.cos(), r * a.sin(), 0.0);
+
+    let focus = ray.origin + ray.direction * focus_distance;
+    ray.direction = (focus - lens) / focus_distance;
+    ray.origin = lens;
+}
+
+// Same as `pcg` in `random.glsl`
+fn pcg(state: &mut u32) -> u32 {
+    *state = state.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((*state >> ((*state >> 28) + 4)) ^ *state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+fn random(state: &mut u32) -> f32 {
+    pcg(state) as f32 / 4294967296.0
+}
+
+fn intersect_aabb(
+    ray: &Ray,
+    inv_direction: &Vec3,
+    lo: &Vec3,
+    hi: &Vec3,
+    t_min: f32,
+    closest: f32,
+) -> bool {
+    let t0 = (lo - ray.origin).component_mul(inv_direction);
+    let t1 = (hi - ray.origin).component_mul(inv_direction);
+    let t_near = t0.inf(&t1);
+    let t_far = t0.sup(&t1);
+
+    let enter = t_near.x.max(t_near.y).max(t_near.z.max(t_min));
+    let exit = t_far.x.min(t_far.y).min(t_far.z.min(closest));
+    enter <= exit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        AaBox, Bvh, Capsule, Cylinder, Mesh, MeshInstance, OrientedBox, Plane, Primitive, Sphere,
+        Torus, Vertex,
+    };
+    use std::sync::Arc;
+
+    fn render(spheres: Vec<Sphere>, camera: Camera) -> RgbaImage {
+        let scene = Scene {
+            camera,
+            spheres,
+            primitives: vec![],
+            meshes: vec![],
+            materials: vec![Material::Diffuse {
+                albedo: vec3(1.0, 0.0, 0.0),
+            }],
+            lights: vec![],
+        };
+        ReferenceRenderer::new([64, 48], &scene).render(&scene.camera)
+    }
+
+    fn with_lights(spheres: Vec<Sphere>, lights: Vec<Light>) -> ReferenceRenderer {
+        let scene = Scene {
+            camera: Camera::default(),
+            spheres,
+            primitives: vec![],
+            meshes: vec![],
+            materials: vec![],
+            lights,
+        };
+        ReferenceRenderer::new([4, 4], &scene)
+    }
+
+    #[test]
+    fn empty_scene_is_sky() {
+        let image = render(vec![], Camera::default());
+
+        // The gradient gets bluer towards the top of the frame
+        let top = image.get_pixel(32, 0);
+        let bottom = image.get_pixel(32, 47);
+        assert!(top[0] < bottom[0]);
+        assert_eq!(top[2], u8::MAX);
+    }
+
+    #[test]
+    fn sphere_in_front_is_hit() {
+        let image = render(
+            vec![Sphere::new(vec3(0.0, 0.0, -5.0), 1.0).with_material(0)],
+            Camera::default(),
+        );
+
+        let center = image.get_pixel(32, 24);
+        assert!(center[0] > 200);
+        assert_eq!(center[1], 0);
+        assert_eq!(center[2], 0);
+    }
+
+    #[test]
+    fn spheres_outside_the_depth_range_are_missed() {
+        let sky = render(vec![], Camera::default());
+        let beyond_far = render(
+            vec![Sphere::new(vec3(0.0, 0.0, -12.0), 1.0)],
+            Camera {
+                far: 10.0,
+                ..Default::default()
+            },
+        );
+        let before_near = render(
+            vec![Sphere::new(vec3(0.0, 0.0, -5.0), 1.0)],
+            Camera {
+                near: 7.0,
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(beyond_far, sky);
+        assert_eq!(before_near, sky);
+    }
+
+    #[test]
+    fn orthographic_spheres_keep_their_size_with_distance() {
+        let camera = Camera {
+            projection: Projection::Orthographic { height: 4.0 },
+            ..Default::default()
+        };
+        let covered = |z: f32| {
+            let image = render(
+                vec![Sphere::new(vec3(0.0, 0.0, z), 1.0).with_material(0)],
+                camera.clone(),
+            );
+            image.pixels().filter(|p| p[1] == 0).count()
+        };
+
+        // Half the frame's height across, a circle of about 450 pixels
+        let near = covered(-5.0);
+        assert_eq!(near, covered(-50.0));
+        assert!((400..500).contains(&near), "{near} pixels");
+    }
+
+    #[test]
+    fn panoramas_see_behind_the_camera() {
+        let behind = vec![Sphere::new(vec3(0.0, 0.0, 5.0), 1.0).with_material(0)];
+        let red = |image: &RgbaImage, x, y| image.get_pixel(x, y)[1] == 0;
+
+        let perspective = render(behind.clone(), Camera::default());
+        assert!(!perspective.pixels().any(|p| p[1] == 0));
+
+        // Straight behind is at the left and right edges, halfway up
+        let panorama = render(
+            behind.clone(),
+            Camera {
+                projection: Projection::Equirectangular,
+                ..Default::default()
+            },
+        );
+        assert!(red(&panorama, 0, 24) && red(&panorama, 63, 24));
+        assert!(!red(&panorama, 32, 24));
+
+        // Seeing the whole sphere up the frame, straight behind is the circle touching the top
+        let fisheye = render(
+            behind,
+            Camera {
+                projection: Projection::Fisheye,
+                fov: 2.0 * PI,
+                ..Default::default()
+            },
+        );
+        assert!(red(&fisheye, 32, 0));
+        assert!(!red(&fisheye, 32, 12));
+        // Corners are further than the view reaches
+        assert_eq!(fisheye.get_pixel(0, 0), &Rgba([0, 0, 0, u8::MAX]));
+    }
+
+    #[test]
+    fn only_the_focus_distance_stays_sharp() {
+        // How many pixels the lens turns from red to sky or back, with a red wall
+        // covering the left of the frame at the given depth
+        let blurred = |depth: f32| {
+            let wall = AaBox::new(
+                vec3(0.05, -100.0, -depth - 0.01),
+                vec3(100.0, 100.0, -depth),
+            );
+            let scene = Scene {
+                primitives: vec![wall.with_material(0).into()],
+                materials: vec![Material::Diffuse {
+                    albedo: vec3(1.0, 0.0, 0.0),
+                }],
+                ..Scene::empty()
+            };
+            let renderer = ReferenceRenderer::new([64, 48], &scene);
+            let pinhole = renderer.render(&Camera::default());
+            let lens = renderer.render(&Camera {
+                aperture: 0.3,
+                focus_distance: 5.0,
+                ..Default::default()
+            });
+            pinhole
+                .pixels()
+                .zip(lens.pixels())
+                .filter(|(a, b)| (a[1] == 0) != (b[1] == 0))
+                .count()
+        };
+
+        assert_eq!(blurred(5.0), 0);
+        assert!(blurred(1.0) > 48);
+    }
+
+    #[test]
+    fn bvh_matches_linear_search() {
+        let scene = Scene::grid();
+        let camera = Camera {
+            position: vec3(-8.0, -8.0, -20.0),
+            ..Default::default()
+        }
+        .looking_at(&vec3(4.0, 6.0, 0.0), &Vec3::y());
+        let renderer = ReferenceRenderer::new([40, 30], &scene);
+
+        // With a single leaf every sphere is tested, like the original linear loop
+        let mut geometry = Geometry::build(&scene);
+        geometry.primitives = scene.spheres.iter().map(Sphere::raw).collect();
+        geometry.bvh = Bvh {
+            nodes: vec![crate::BvhNode {
+                min: Vec3::repeat(-1000.0),
+                max: Vec3::repeat(1000.0),
+                first: 0,
+                count: scene.spheres.len() as u32,
+            }],
+            indices: (0..scene.spheres.len() as u32).collect(),
+        };
+        let linear = ReferenceRenderer {
+            size: renderer.size,
+            geometry,
+            materials: vec![],
+            lights: vec![],
+        };
+
+        assert_eq!(renderer.render(&camera), linear.render(&camera));
+    }
+
+    #[test]
+    fn no_lights_leave_the_preview_shading() {
+        let renderer = with_lights(vec![], vec![]);
+        let up = vec3(0.0, 1.0, 0.0);
+
+        assert!(renderer
+            .direct_light(&Vec3::zeros(), &up, &Vec3::repeat(1.0))
+            .is_none());
+    }
+
+    #[test]
+    fn blocked_light_casts_shadow() {
+        let renderer = with_lights(
+            vec![Sphere::new(vec3(0.0, 2.0, 0.0), 0.5)],
+            vec![Light::Point {
+                position: vec3(0.0, 4.0, 0.0),
+                colour: Vec3::repeat(1.0),
+                intensity: 10.0,
+            }],
+        );
+        let up = vec3(0.0, 1.0, 0.0);
+        let white = Vec3::repeat(1.0);
+
+        let shadowed = renderer.direct_light(&Vec3::zeros(), &up, &white);
+        let lit = renderer.direct_light(&vec3(3.0, 0.0, 0.0), &up, &white);
+        assert_eq!(shadowed, Some(Vec3::zeros()));
+        assert!(lit.unwrap().x > 0.0);
+    }
+
+    #[test]
+    fn spot_light_only_lights_its_cone() {
+        let renderer = with_lights(
+            vec![],
+            vec![Light::Spot {
+                position: vec3(0.0, 4.0, 0.0),
+                direction: vec3(0.0, -1.0, 0.0),
+                angle: 0.3,
+                colour: Vec3::repeat(1.0),
+                intensity: 10.0,
+            }],
+        );
+        let up = vec3(0.0, 1.0, 0.0);
+        let white = Vec3::repeat(1.0);
+
+        let inside = renderer.direct_light(&Vec3::zeros(), &up, &white);
+        let outside = renderer.direct_light(&vec3(4.0, 0.0, 0.0), &up, &white);
+        assert!(inside.unwrap().x > 0.0);
+        assert_eq!(outside, Some(Vec3::zeros()));
+    }
+
+    // Shoots a ray down the Z axis from z = 5 and returns the distance and normal of the hit
+    fn shoot(primitive: impl Into<Primitive>, x: f32, y: f32) -> Option<(f32, Vec3)> {
+        let ray = Ray {
+            origin: vec3(x, y, 5.0),
+            direction: vec3(0.0, 0.0, -1.0),
+        };
+        intersect_alone(primitive.into(), &ray, 0.0)
+    }
+
+    // Intersects a scene holding only the given primitive
+    fn intersect_alone(primitive: Primitive, ray: &Ray, t_min: f32) -> Option<(f32, Vec3)> {
+        let mut scene = Scene::grid();
+        scene.spheres.clear();
+        scene.primitives = vec![primitive];
+        let geometry = Geometry::build(&scene);
+        intersect_primitive(&geometry, &geometry.primitives[0], ray, t_min, 100.0)
+    }
+
+    fn assert_hit(hit: Option<(f32, Vec3)>, distance: f32, normal: Vec3) {
+        let (t, n) = hit.expect("expected a hit");
+        assert!(
+            (t - distance).abs() < 1e-3,
+            "hit at {t}, expected {distance}"
+        );
+        assert!(
+            (n - normal).magnitude() < 1e-3,
+            "normal {n:?}, expected {normal:?}"
+        );
+    }
+
+    #[test]
+    fn plane_is_hit_from_either_side() {
+        let facing = Plane::new(vec3(0.0, 0.0, 2.0), 2.0);
+        assert_hit(shoot(facing, 3.0, -7.0), 4.0, vec3(0.0, 0.0, 1.0));
+
+        // Planes have no inside, the normal still points the way it was given
+        let away = Plane::new(vec3(0.0, 0.0, -1.0), -1.0);
+        assert_hit(shoot(away, 0.0, 0.0), 4.0, vec3(0.0, 0.0, -1.0));
+
+        let parallel = Plane::new(vec3(1.0, 0.0, 0.0), 0.0);
+        assert!(shoot(parallel, 1.0, 0.0).is_none());
+    }
+
+    #[test]
+    fn boxes_are_hit_on_their_faces() {
+        let aa_box = AaBox::new(vec3(-1.0, -1.0, -1.0), vec3(1.0, 1.0, 1.0));
+        assert_hit(shoot(aa_box.clone(), 0.5, 0.5), 4.0, vec3(0.0, 0.0, 1.0));
+        assert!(shoot(aa_box, 1.5, 0.0).is_none());
+
+        // Turned by 45 degrees around Y, an edge points at the ray
+        let turned = OrientedBox::new(
+            Vec3::zeros(),
+            vec3(1.0, 1.0, 1.0),
+            vec3(0.0, std::f32::consts::FRAC_PI_4, 0.0),
+        );
+        let (t, n) = shoot(turned.clone(), 0.1, 0.0).unwrap();
+        assert!((t - (5.0 - 2f32.sqrt() + 0.1)).abs() < 1e-3);
+        assert!((n.z - 0.5f32.sqrt()).abs() < 1e-3 && n.y.abs() < 1e-3);
+        assert!(shoot(turned, 1.3, 0.0).is_some());
+    }
+
+    #[test]
+    fn cylinder_is_hit_on_caps_and_sides() {
+        let along_z = Cylinder::new(vec3(0.0, 0.0, -1.0), vec3(0.0, 0.0, 1.0), 0.5);
+        assert_hit(shoot(along_z.clone(), 0.2, 0.2), 4.0, vec3(0.0, 0.0, 1.0));
+        assert!(shoot(along_z, 0.6, 0.0).is_none());
+
+        let along_x = Cylinder::new(vec3(-1.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 0.5);
+        assert_hit(shoot(along_x.clone(), 0.0, 0.0), 4.5, vec3(0.0, 0.0, 1.0));
+        assert!(shoot(along_x, 1.1, 0.0).is_none());
+    }
+
+    #[test]
+    fn capsule_ends_are_round() {
+        let capsule = Capsule::new(vec3(-1.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 0.5);
+        assert_hit(shoot(capsule.clone(), 0.0, 0.0), 4.5, vec3(0.0, 0.0, 1.0));
+
+        // Past the end of the segment the surface curves away like a sphere
+        let (t, n) = shoot(capsule.clone(), 1.3, 0.0).unwrap();
+        assert!((t - (5.0 - 0.4)).abs() < 1e-3);
+        assert!((n - vec3(0.6, 0.0, 0.8)).magnitude() < 1e-3);
+        assert!(shoot(capsule, 1.6, 0.0).is_none());
+    }
+
+    #[test]
+    fn torus_has_a_hole() {
+        // Standing up so the ring faces the ray
+        let torus = Torus::new(Vec3::zeros(), 1.0, 0.25).with_rotation(vec3(
+            std::f32::consts::FRAC_PI_2,
+            0.0,
+            0.0,
+        ));
+        assert!(shoot(torus.clone(), 0.0, 0.0).is_none());
+        assert_hit(shoot(torus.clone(), 1.0, 0.0), 4.75, vec3(0.0, 0.0, 1.0));
+        assert_hit(shoot(torus, 0.0, -1.0), 4.75, vec3(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn rays_inside_leave_through_the_far_side() {
+        let ray = Ray {
+            origin: Vec3::zeros(),
+            direction: vec3(0.0, 0.0, -1.0),
+        };
+        let primitives: [Primitive; 3] = [
+            AaBox::new(vec3(-1.0, -1.0, -1.0), vec3(1.0, 1.0, 1.0)).into(),
+            Cylinder::new(vec3(0.0, 0.0, -1.0), vec3(0.0, 0.0, 1.0), 0.5).into(),
+            Capsule::new(vec3(0.0, 0.0, -0.5), vec3(0.0, 0.0, 0.5), 0.5).into(),
+        ];
+
+        for primitive in primitives {
+            let hit = intersect_alone(primitive, &ray, 0.001);
+            assert_hit(hit, 1.0, vec3(0.0, 0.0, -1.0));
+        }
+    }
+
+    #[test]
+    fn mesh_instances_are_hit_with_smooth_normals() {
+        // A unit quad facing +Z, with its normals leaning out towards +X and -X
+        let vertex = |x: f32, y: f32| Vertex {
+            position: vec3(x, y, 0.0),
+            normal: vec3(x - 0.5, 0.0, 1.0).normalize(),
+        };
+        let quad = Arc::new(
+            Mesh::new(
+                vec![
+                    vertex(0.0, 0.0),
+                    vertex(1.0, 0.0),
+                    vertex(1.0, 1.0),
+                    vertex(0.0, 1.0),
+                ],
+                vec![0, 1, 2, 0, 2, 3],
+            )
+            .unwrap(),
+        );
+
+        let mut scene = Scene::grid();
+        scene.spheres.clear();
+        scene.meshes =
+            vec![MeshInstance::new(quad).with_transform(vec3(-1.0, -1.0, 1.0), Vec3::zeros(), 2.0)];
+        let geometry = Geometry::build(&scene);
+        let shoot = |x: f32| {
+            let ray = Ray {
+                origin: vec3(x, 0.0, 5.0),
+                direction: vec3(0.0, 0.0, -1.0),
+            };
+            intersect_primitive(&geometry, &geometry.primitives[0], &ray, 0.0, 100.0)
+        };
+
+        assert_hit(shoot(0.0), 4.0, vec3(0.0, 0.0, 1.0));
+        let (_, right) = shoot(0.9).unwrap();
+        let (_, left) = shoot(-0.9).unwrap();
+        assert!(right.x > 0.3 && left.x < -0.3);
+        assert!(shoot(1.1).is_none());
+    }
+}
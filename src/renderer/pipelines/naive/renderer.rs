@@ -1,12 +1,14 @@
-extern crate nalgebra_glm as glm;
-
 use std::sync::Arc;
 
-use crate::{naive::constants::RendererConstants, Camera, RawCamera};
-use glm::{vec3, Vec3};
+use crate::{naive::constants::RendererConstants, Camera, RawCamera, Scene};
+use image::RgbaImage;
+use log::debug;
 use vulkano::{
-    buffer::{Buffer, BufferCreateInfo, BufferUsage},
-    command_buffer::{AutoCommandBufferBuilder, BlitImageInfo, CommandBufferUsage},
+    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{
+        AutoCommandBufferBuilder, BlitImageInfo, CommandBufferExecFuture, CommandBufferUsage,
+        CopyImageToBufferInfo, PrimaryAutoCommandBuffer,
+    },
     descriptor_set::{
         allocator::StandardDescriptorSetAllocator,
         layout::{
@@ -16,21 +18,92 @@ use vulkano::{
         PersistentDescriptorSet, WriteDescriptorSet,
     },
     device::Queue,
-    image::{view::ImageView, ImageDimensions, ImageUsage, StorageImage, SwapchainImage},
+    image::{
+        view::ImageView, ImageAccess, ImageDimensions, ImageUsage, StorageImage, SwapchainImage,
+    },
     memory::allocator::{AllocationCreateInfo, MemoryUsage},
     pipeline::{
         layout::{PipelineLayoutCreateInfo, PushConstantRange},
         ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout,
     },
-    shader::ShaderStages,
-    swapchain::{self, Surface, Swapchain, SwapchainCreateInfo, SwapchainPresentInfo},
-    sync::{self, GpuFuture},
+    shader::{ShaderModule, ShaderStages},
+    swapchain::{
+        self, AcquireError, CompositeAlpha, PresentFuture, Surface, Swapchain,
+        SwapchainAcquireFuture, SwapchainCreateInfo, SwapchainCreationError, SwapchainPresentInfo,
+    },
+    sync::{
+        self,
+        future::{FenceSignalFuture, JoinFuture},
+        FlushError, GpuFuture,
+    },
+};
+
+use crate::{Capabilities, Renderer, RenderingContext, WreckageError};
+
+use super::{
+    shader, Geometry, Light, Material, RawBvhNode, RawLight, RawMaterial, RawPrimitive, RawVertex,
 };
 
-use crate::RenderingContext;
+/// Everything the pipeline uses that doesn't depend on the size of the frame
+pub(crate) struct Resources {
+    pub(crate) descriptor_set_allocator: StandardDescriptorSetAllocator,
+    pub(crate) descriptor_set_layout: Arc<DescriptorSetLayout>,
+    pub(crate) pipeline_layout: Arc<PipelineLayout>,
+    pub(crate) shader: Arc<ShaderModule>,
+    pub(crate) scene: SceneBuffers,
+}
+
+pub(crate) type FrameFence = Arc<
+    FenceSignalFuture<
+        PresentFuture<
+            CommandBufferExecFuture<
+                JoinFuture<Box<dyn GpuFuture + Send + Sync>, SwapchainAcquireFuture>,
+            >,
+        >,
+    >,
+>;
+
+/// The size of a new swapchain along with it and its images
+pub(crate) type SwapchainParts = ([u32; 2], Arc<Swapchain>, Vec<Arc<SwapchainImage>>);
+
+/// The scene as uploaded to the GPU, at the same bindings in every pipeline
+pub(crate) struct SceneBuffers {
+    pub(crate) primitives: Subbuffer<[RawPrimitive]>,
+    pub(crate) materials: Subbuffer<[RawMaterial]>,
+    pub(crate) bvh: Subbuffer<[RawBvhNode]>,
+    pub(crate) lights: Subbuffer<[RawLight]>,
+    pub(crate) vertices: Subbuffer<[RawVertex]>,
+    pub(crate) indices: Subbuffer<[u32]>,
+    pub(crate) mesh_bvh: Subbuffer<[RawBvhNode]>,
+}
 
-use super::{shader, Sphere};
+impl SceneBuffers {
+    pub(crate) fn descriptor_writes(&self) -> [WriteDescriptorSet; 7] {
+        [
+            WriteDescriptorSet::buffer(1, self.primitives.clone()),
+            WriteDescriptorSet::buffer(2, self.materials.clone()),
+            WriteDescriptorSet::buffer(3, self.bvh.clone()),
+            WriteDescriptorSet::buffer(5, self.lights.clone()),
+            WriteDescriptorSet::buffer(6, self.vertices.clone()),
+            WriteDescriptorSet::buffer(7, self.indices.clone()),
+            WriteDescriptorSet::buffer(8, self.mesh_bvh.clone()),
+        ]
+    }
+}
 
+/// Resources owned by a single frame in flight, reused once its fence is signalled
+pub(crate) struct Frame {
+    pub(crate) out_image: Arc<StorageImage>,
+    pub(crate) descriptors: Arc<PersistentDescriptorSet>,
+    pub(crate) fence: Option<FrameFence>,
+}
+
+/// Frames the CPU may record ahead of the GPU unless told otherwise
+pub const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
+pub const MAX_FRAMES_IN_FLIGHT: usize = 3;
+
+/// Traces every pixel against the primitives of the scene in a single compute pass,
+/// shading hits with a preview of their material or by their normal.
 pub struct NaiveRenderer {
     pub(crate) ctx: Arc<RenderingContext>,
 
@@ -40,106 +113,55 @@ pub struct NaiveRenderer {
 
     // Dataflow
     pub(crate) queue: Arc<Queue>,
-    pub(crate) out_image: Arc<StorageImage>,
+    pub(crate) resources: Resources,
     pub(crate) pipeline: Arc<ComputePipeline>,
-    pub(crate) descriptors: Arc<PersistentDescriptorSet>,
+    pub(crate) frames: Vec<Frame>,
+    pub(crate) frame_index: usize,
+    pub(crate) previous_frame_end: Option<Box<dyn GpuFuture + Send + Sync>>,
 
-    // Presentation
-    pub(crate) swapchain: Arc<Swapchain>,
+    // Presentation, missing when rendering headless
+    pub(crate) swapchain: Option<Arc<Swapchain>>,
     pub(crate) swapchain_images: Vec<Arc<SwapchainImage>>,
+    pub(crate) recreate_swapchain: bool,
 
     // Controls
-    pub(crate) position: Vec3,
-    pub(crate) rotation: Vec3,
+    pub(crate) camera: Camera,
 }
 
 impl NaiveRenderer {
-    pub fn new(ctx: Arc<RenderingContext>, surface: Arc<Surface>) -> Self {
-        // Capabilities of the surface of the device
-        let caps = ctx
-            .physical_device
-            .surface_capabilities(&surface, Default::default())
-            .expect("failed to get surface capabilities");
-
-        // Dimensions of the surface to draw on
-        let surface_size = [800, 600];
-        let scale_factor = 4u32;
-        let viewport_size = [800 / scale_factor, 600 / scale_factor];
-
-        let composite_alpha = caps.supported_composite_alpha.into_iter().next().unwrap();
-        let image_format = Some(
-            ctx.physical_device
-                .surface_formats(&surface, Default::default())
-                .unwrap()[0]
-                .0,
-        );
+    /// Creates a renderer presenting to `surface`, tracing at a quarter of its resolution
+    pub fn new(
+        ctx: Arc<RenderingContext>,
+        surface: Arc<Surface>,
+        scene: &Scene,
+    ) -> Result<Self, WreckageError> {
+        let (surface_size, swapchain, images) = create_swapchain(&ctx, surface)?;
+        Self::build(ctx, surface_size, 4, Some(swapchain), images, scene)
+    }
 
-        let (swapchain, images) = Swapchain::new(
-            ctx.device.clone(),
-            surface.clone(),
-            SwapchainCreateInfo {
-                min_image_count: caps.min_image_count + 1, // How many buffers to use in the swapchain
-                image_format,
-                image_extent: surface_size.into(),
-                image_usage: ImageUsage::TRANSFER_DST, // What the images are going to be used for
-                composite_alpha,
-                ..Default::default()
-            },
-        )
-        .unwrap();
+    /// Creates a renderer without a window, frames can only be read back with `capture`
+    pub fn headless(
+        ctx: Arc<RenderingContext>,
+        size: [u32; 2],
+        scene: &Scene,
+    ) -> Result<Self, WreckageError> {
+        Self::build(ctx, size, 1, None, vec![], scene)
+    }
 
+    fn build(
+        ctx: Arc<RenderingContext>,
+        surface_size: [u32; 2],
+        scale_factor: u32,
+        swapchain: Option<Arc<Swapchain>>,
+        swapchain_images: Vec<Arc<SwapchainImage>>,
+        scene: &Scene,
+    ) -> Result<Self, WreckageError> {
         // Queue to push the commands into
-        let queue = ctx.queues.iter().next().unwrap().clone();
-
-        // The constants set up by the renderer
-        let consts = RendererConstants {
-            aspect_ratio: viewport_size[0] as f32 / viewport_size[1] as f32,
-            width: viewport_size[0],
-            height: viewport_size[1],
-            min_depth: 0f32,
-            max_depth: 12f32,
-        };
-
-        // The buffer to draw onto
-        let out_image = StorageImage::new(
-            &ctx.memory_allocator,
-            ImageDimensions::Dim2d {
-                width: viewport_size[0],
-                height: viewport_size[1],
-                array_layers: 1,
-            },
-            vulkano::format::Format::R8G8B8A8_UNORM,
-            Some(queue.queue_family_index()),
-        )
-        .unwrap();
-
-        let mut spheres = vec![];
-        for x in 0..16 {
-            for y in 0..16 {
-                for z in 0..16 {
-                    spheres.push(Sphere::new(
-                        vec3(x as f32, y as f32, z as f32),
-                        ((x * y + z) % 5 + 1) as f32 / 20.0,
-                    ));
-                }
-            }
-        }
-
-        // The buffer to store spheres in
-        let sphere_buffer = Buffer::from_iter(
-            &ctx.memory_allocator,
-            BufferCreateInfo {
-                usage: BufferUsage::UNIFORM_BUFFER,
-                ..Default::default()
-            },
-            AllocationCreateInfo {
-                usage: MemoryUsage::Upload,
-                ..Default::default()
-            },
-            spheres.into_iter()
-            .map(Sphere::raw),
-        )
-        .unwrap();
+        let queue = ctx
+            .queues
+            .first()
+            .ok_or(WreckageError::NoQueueFamily)?
+            .clone();
 
         // Allocator for the descriptors
         let descriptor_set_allocator = StandardDescriptorSetAllocator::new(ctx.device.clone());
@@ -163,7 +185,7 @@ impl NaiveRenderer {
                         DescriptorSetLayoutBinding {
                             stages: ShaderStages::COMPUTE,
                             ..DescriptorSetLayoutBinding::descriptor_type(
-                                DescriptorType::UniformBuffer,
+                                DescriptorType::StorageBuffer,
                             )
                         },
                     ),
@@ -172,7 +194,52 @@ impl NaiveRenderer {
                         DescriptorSetLayoutBinding {
                             stages: ShaderStages::COMPUTE,
                             ..DescriptorSetLayoutBinding::descriptor_type(
-                                DescriptorType::UniformBuffer,
+                                DescriptorType::StorageBuffer,
+                            )
+                        },
+                    ),
+                    (
+                        3,
+                        DescriptorSetLayoutBinding {
+                            stages: ShaderStages::COMPUTE,
+                            ..DescriptorSetLayoutBinding::descriptor_type(
+                                DescriptorType::StorageBuffer,
+                            )
+                        },
+                    ),
+                    (
+                        5,
+                        DescriptorSetLayoutBinding {
+                            stages: ShaderStages::COMPUTE,
+                            ..DescriptorSetLayoutBinding::descriptor_type(
+                                DescriptorType::StorageBuffer,
+                            )
+                        },
+                    ),
+                    (
+                        6,
+                        DescriptorSetLayoutBinding {
+                            stages: ShaderStages::COMPUTE,
+                            ..DescriptorSetLayoutBinding::descriptor_type(
+                                DescriptorType::StorageBuffer,
+                            )
+                        },
+                    ),
+                    (
+                        7,
+                        DescriptorSetLayoutBinding {
+                            stages: ShaderStages::COMPUTE,
+                            ..DescriptorSetLayoutBinding::descriptor_type(
+                                DescriptorType::StorageBuffer,
+                            )
+                        },
+                    ),
+                    (
+                        8,
+                        DescriptorSetLayoutBinding {
+                            stages: ShaderStages::COMPUTE,
+                            ..DescriptorSetLayoutBinding::descriptor_type(
+                                DescriptorType::StorageBuffer,
                             )
                         },
                     ),
@@ -180,11 +247,10 @@ impl NaiveRenderer {
                 .into(),
                 ..Default::default()
             },
-        )
-        .unwrap();
+        )?;
 
         // Compiled shader to display colour with
-        let shader = shader(ctx.device.clone());
+        let shader = shader(ctx.device.clone())?;
 
         // Push constants
         let push_constants = vec![PushConstantRange {
@@ -201,96 +267,510 @@ impl NaiveRenderer {
                 push_constant_ranges: push_constants,
                 ..Default::default()
             },
-        )
-        .unwrap();
+        )?;
 
-        // The single shader compute pipeline to run the operations inside of
-        let compute_pipeline = ComputePipeline::with_pipeline_layout(
-            ctx.device.clone(),
-            shader.entry_point("main").unwrap(),
-            &consts,
+        let resources = Resources {
+            descriptor_set_allocator,
+            descriptor_set_layout,
             pipeline_layout,
-            None,
-        )
-        .expect("failed to create compute pipeline");
+            shader,
+            scene: upload_scene(&ctx, scene)?,
+        };
 
-        // View of the image for the pipeline to draw on
-        let view = ImageView::new_default(out_image.clone()).unwrap();
+        let viewport_size = viewport_size(surface_size, scale_factor);
+        let pipeline = Self::create_pipeline(&ctx, &resources, viewport_size)?;
+        let frames = (0..DEFAULT_FRAMES_IN_FLIGHT)
+            .map(|_| Self::create_frame(&ctx, &queue, &resources, viewport_size))
+            .collect::<Result<_, _>>()?;
+        let previous_frame_end = Some(sync::now(ctx.device.clone()).boxed_send_sync());
 
-        // Descriptors to push into the pipeline
-        let descriptor_set = PersistentDescriptorSet::new(
-            &descriptor_set_allocator,
-            descriptor_set_layout.clone(),
-            [
-                WriteDescriptorSet::image_view(0, view),
-                WriteDescriptorSet::buffer(1, sphere_buffer),
-            ],
-        )
-        .unwrap();
-
-        Self {
-            position: Vec3::identity(),
-            rotation: Vec3::identity(),
+        Ok(Self {
+            camera: scene.camera.clone(),
             ctx,
             scale_factor,
             viewport_size,
             surface_size,
             swapchain,
-            swapchain_images: images,
-            out_image,
-            pipeline: compute_pipeline,
-            descriptors: descriptor_set,
+            swapchain_images,
+            recreate_swapchain: false,
             queue,
+            resources,
+            pipeline,
+            frames,
+            frame_index: 0,
+            previous_frame_end,
+        })
+    }
+
+    /// Creates the pipeline, which bakes in the size of the frame
+    fn create_pipeline(
+        ctx: &RenderingContext,
+        resources: &Resources,
+        viewport_size: [u32; 2],
+    ) -> Result<Arc<ComputePipeline>, WreckageError> {
+        // The constants set up by the renderer
+        let consts = RendererConstants {
+            aspect_ratio: viewport_size[0] as f32 / viewport_size[1] as f32,
+            width: viewport_size[0],
+            height: viewport_size[1],
+        };
+
+        // The single shader compute pipeline to run the operations inside of
+        let pipeline = ComputePipeline::with_pipeline_layout(
+            ctx.device.clone(),
+            resources
+                .shader
+                .entry_point("main")
+                .expect("shaders are compiled with a main function"),
+            &consts,
+            resources.pipeline_layout.clone(),
+            None,
+        )?;
+        ctx.set_name(&*pipeline, "naive pipeline");
+        Ok(pipeline)
+    }
+
+    /// Creates the image a frame is drawn onto and the descriptors pointing at it
+    fn create_frame(
+        ctx: &RenderingContext,
+        queue: &Queue,
+        resources: &Resources,
+        viewport_size: [u32; 2],
+    ) -> Result<Frame, WreckageError> {
+        // The buffer to draw onto
+        let out_image = StorageImage::new(
+            &ctx.memory_allocator,
+            ImageDimensions::Dim2d {
+                width: viewport_size[0],
+                height: viewport_size[1],
+                array_layers: 1,
+            },
+            vulkano::format::Format::R8G8B8A8_UNORM,
+            Some(queue.queue_family_index()),
+        )?;
+        ctx.set_name(&**out_image.inner().image, "naive frame");
+
+        // View of the image for the pipeline to draw on
+        let view = ImageView::new_default(out_image.clone())?;
+
+        // Descriptors to push into the pipeline
+        let descriptors = PersistentDescriptorSet::new(
+            &resources.descriptor_set_allocator,
+            resources.descriptor_set_layout.clone(),
+            [WriteDescriptorSet::image_view(0, view)]
+                .into_iter()
+                .chain(resources.scene.descriptor_writes()),
+        )?;
+
+        Ok(Frame {
+            out_image,
+            descriptors,
+            fence: None,
+        })
+    }
+
+    // Blocks until the GPU is done with every frame
+    fn wait_idle(&mut self) -> Result<(), WreckageError> {
+        for frame in &mut self.frames {
+            if let Some(fence) = frame.fence.take() {
+                fence.wait(None)?;
+            }
         }
+        Ok(())
     }
 
-    pub fn draw(&self) {
-        let mut builder = AutoCommandBufferBuilder::primary(
-            &self.ctx.command_buffer_allocator,
-            self.queue.queue_family_index(),
-            CommandBufferUsage::OneTimeSubmit,
-        )
-        .unwrap();
+    fn recreate_target(&mut self) -> Result<(), WreckageError> {
+        self.wait_idle()?;
+        self.viewport_size = viewport_size(self.surface_size, self.scale_factor);
+        self.pipeline = Self::create_pipeline(&self.ctx, &self.resources, self.viewport_size)?;
+        for frame in &mut self.frames {
+            *frame =
+                Self::create_frame(&self.ctx, &self.queue, &self.resources, self.viewport_size)?;
+        }
+        Ok(())
+    }
 
-        let (image_i, _suboptimal, acquire_future) =
-            swapchain::acquire_next_image(self.swapchain.clone(), None).unwrap();
-        let image = self.swapchain_images.get(image_i as usize).unwrap();
+    // Returns false if the swapchain couldn't be recreated at the current size
+    fn recreate_swapchain(&mut self) -> Result<bool, WreckageError> {
+        let Some(swapchain) = &self.swapchain else {
+            return Ok(true);
+        };
+
+        let (swapchain, images) = match swapchain.recreate(SwapchainCreateInfo {
+            image_extent: self.surface_size,
+            ..swapchain.create_info()
+        }) {
+            Ok(r) => r,
+            // Happens while the window is being resized, try again next frame
+            Err(SwapchainCreationError::ImageExtentNotSupported { .. }) => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+
+        debug!(
+            "Recreated swapchain at {}x{}",
+            self.surface_size[0], self.surface_size[1]
+        );
+        self.swapchain = Some(swapchain);
+        self.swapchain_images = images;
+        self.recreate_swapchain = false;
+        self.recreate_target()?;
+        Ok(true)
+    }
 
+    fn record_trace(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        frame: &Frame,
+    ) -> Result<(), WreckageError> {
         builder
             .bind_pipeline_compute(self.pipeline.clone())
             .bind_descriptor_sets(
                 PipelineBindPoint::Compute,
                 self.pipeline.layout().clone(),
                 0u32,
-                self.descriptors.clone(),
+                frame.descriptors.clone(),
             )
-            .push_constants(
-                self.pipeline.layout().clone(),
-                0,
-                Camera {
-                    position: self.position,
-                    rotation: self.rotation,
+            .push_constants(self.pipeline.layout().clone(), 0, self.camera.raw())
+            .dispatch([self.viewport_size[0], self.viewport_size[1], 1])?;
+        Ok(())
+    }
+}
+
+impl Renderer for NaiveRenderer {
+    fn context(&self) -> Arc<RenderingContext> {
+        self.ctx.clone()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            name: "naive",
+            presents: self.swapchain.is_some(),
+            max_frames_in_flight: MAX_FRAMES_IN_FLIGHT,
+            frames_in_flight: self.frames.len(),
+            progressive: false,
+            samples: 1,
+        }
+    }
+
+    fn draw(&mut self) -> Result<(), WreckageError> {
+        // Nothing to draw into while the window is minimised
+        if self.surface_size.contains(&0) {
+            return Ok(());
+        }
+
+        // Free whatever the GPU has finished with since the last frame
+        if let Some(previous_frame_end) = self.previous_frame_end.as_mut() {
+            previous_frame_end.cleanup_finished();
+        }
+
+        if self.recreate_swapchain && !self.recreate_swapchain()? {
+            return Ok(());
+        }
+
+        let swapchain = self.swapchain.clone().ok_or(WreckageError::Headless)?;
+
+        let (image_i, suboptimal, acquire_future) =
+            match swapchain::acquire_next_image(swapchain.clone(), None) {
+                Ok(r) => r,
+                Err(AcquireError::OutOfDate) => {
+                    self.recreate_swapchain = true;
+                    return Err(WreckageError::SwapchainOutOfDate);
                 }
-                .raw(),
-            )
-            .dispatch([self.viewport_size[0], self.viewport_size[1], 1])
-            .unwrap()
-            .blit_image(BlitImageInfo::images(self.out_image.clone(), image.clone()))
-            .unwrap();
+                Err(e) => return Err(e.into()),
+            };
+        if suboptimal {
+            self.recreate_swapchain = true;
+        }
+        let image = &self.swapchain_images[image_i as usize];
 
-        let command_buffer = builder.build().unwrap();
+        // The resources of this frame may only be reused once its last submission is done
+        let frame_i = self.frame_index;
+        if let Some(fence) = self.frames[frame_i].fence.take() {
+            fence.wait(None)?;
+        }
+        let frame = &self.frames[frame_i];
 
-        sync::now(self.ctx.device.clone())
+        let mut builder = AutoCommandBufferBuilder::primary(
+            &self.ctx.command_buffer_allocator,
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+
+        self.record_trace(&mut builder, frame)?;
+        builder.blit_image(BlitImageInfo::images(
+            frame.out_image.clone(),
+            image.clone(),
+        ))?;
+
+        let command_buffer = builder.build()?;
+
+        let previous_frame_end = self
+            .previous_frame_end
+            .take()
+            .unwrap_or_else(|| sync::now(self.ctx.device.clone()).boxed_send_sync());
+
+        let future = previous_frame_end
             .join(acquire_future)
-            .then_execute(self.queue.clone(), command_buffer)
-            .unwrap()
+            .then_execute(self.queue.clone(), command_buffer)?
             .then_swapchain_present(
                 self.queue.clone(),
-                SwapchainPresentInfo::swapchain_image_index(self.swapchain.clone(), image_i),
+                SwapchainPresentInfo::swapchain_image_index(swapchain, image_i),
             )
-            .then_signal_fence_and_flush()
-            .unwrap()
-            .wait(None)
-            .unwrap();
+            .then_signal_fence_and_flush();
+
+        match future {
+            Ok(future) => {
+                let fence = Arc::new(future);
+                self.frames[frame_i].fence = Some(fence.clone());
+                self.previous_frame_end = Some(fence.boxed_send_sync());
+            }
+            Err(FlushError::OutOfDate) => {
+                self.recreate_swapchain = true;
+                self.previous_frame_end =
+                    Some(sync::now(self.ctx.device.clone()).boxed_send_sync());
+                self.frame_index = (frame_i + 1) % self.frames.len();
+                return Err(WreckageError::SwapchainOutOfDate);
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        self.frame_index = (frame_i + 1) % self.frames.len();
+        Ok(())
     }
+
+    fn capture(&mut self) -> Result<RgbaImage, WreckageError> {
+        let [width, height] = self.viewport_size;
+        self.wait_idle()?;
+        let frame = &self.frames[self.frame_index];
+
+        // The buffer to read the frame back into
+        let readback = Buffer::new_slice::<u8>(
+            &self.ctx.memory_allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                usage: MemoryUsage::Download,
+                ..Default::default()
+            },
+            (width * height * 4) as u64,
+        )?;
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            &self.ctx.command_buffer_allocator,
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+
+        self.record_trace(&mut builder, frame)?;
+        builder.copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+            frame.out_image.clone(),
+            readback.clone(),
+        ))?;
+
+        let command_buffer = builder.build()?;
+
+        sync::now(self.ctx.device.clone())
+            .then_execute(self.queue.clone(), command_buffer)?
+            .then_signal_fence_and_flush()?
+            .wait(None)?;
+
+        let mut frame = RgbaImage::from_raw(width, height, readback.read()?.to_vec())
+            .expect("readback buffer is sized to the frame");
+
+        // The shader stores depth in alpha, which isn't meant to be seen
+        for pixel in frame.pixels_mut() {
+            pixel[3] = u8::MAX;
+        }
+
+        Ok(frame)
+    }
+
+    fn resize(&mut self, surface_size: [u32; 2]) -> Result<(), WreckageError> {
+        if surface_size == self.surface_size {
+            return Ok(());
+        }
+
+        self.surface_size = surface_size;
+        if self.swapchain.is_some() {
+            self.recreate_swapchain = true;
+            Ok(())
+        } else {
+            self.recreate_target()
+        }
+    }
+
+    fn set_camera(&mut self, camera: &Camera) {
+        self.camera = camera.clone();
+    }
+
+    fn set_scene(&mut self, scene: &Scene) -> Result<(), WreckageError> {
+        self.wait_idle()?;
+        self.resources.scene = upload_scene(&self.ctx, scene)?;
+        // The descriptors of every frame point at the old buffers
+        self.recreate_target()
+    }
+
+    fn set_lights(&mut self, lights: &[Light]) -> Result<(), WreckageError> {
+        self.wait_idle()?;
+        if update_lights(&self.ctx, &mut self.resources.scene, lights)? {
+            self.recreate_target()?;
+        }
+        Ok(())
+    }
+
+    fn set_frames_in_flight(&mut self, count: usize) -> Result<(), WreckageError> {
+        let count = count.clamp(1, MAX_FRAMES_IN_FLIGHT);
+        if count == self.frames.len() {
+            return Ok(());
+        }
+
+        self.wait_idle()?;
+        self.frames.truncate(count);
+        while self.frames.len() < count {
+            self.frames.push(Self::create_frame(
+                &self.ctx,
+                &self.queue,
+                &self.resources,
+                self.viewport_size,
+            )?);
+        }
+        self.frame_index = 0;
+        Ok(())
+    }
+}
+
+/// Creates a swapchain the size of the surface, which frames are blitted onto
+pub(crate) fn create_swapchain(
+    ctx: &RenderingContext,
+    surface: Arc<Surface>,
+) -> Result<SwapchainParts, WreckageError> {
+    // Capabilities of the surface of the device
+    let caps = ctx
+        .physical_device
+        .surface_capabilities(&surface, Default::default())?;
+
+    // Dimensions of the surface to draw on
+    let surface_size = caps.current_extent.unwrap_or([800, 600]);
+
+    // Surfaces support at least one of each
+    let composite_alpha = caps
+        .supported_composite_alpha
+        .into_iter()
+        .next()
+        .unwrap_or(CompositeAlpha::Opaque);
+    let image_format = ctx
+        .physical_device
+        .surface_formats(&surface, Default::default())?
+        .first()
+        .map(|(format, _)| *format);
+
+    let (swapchain, images) = Swapchain::new(
+        ctx.device.clone(),
+        surface,
+        SwapchainCreateInfo {
+            min_image_count: caps.min_image_count + 1, // How many buffers to use in the swapchain
+            image_format,
+            image_extent: surface_size,
+            image_usage: ImageUsage::TRANSFER_DST, // What the images are going to be used for
+            composite_alpha,
+            ..Default::default()
+        },
+    )?;
+
+    Ok((surface_size, swapchain, images))
+}
+
+pub(crate) fn viewport_size(surface_size: [u32; 2], scale_factor: u32) -> [u32; 2] {
+    surface_size.map(|side| (side / scale_factor).max(1))
+}
+
+/// Uploads the primitives, meshes, materials and lights of the scene and the hierarchies over them
+pub(crate) fn upload_scene(
+    ctx: &RenderingContext,
+    scene: &Scene,
+) -> Result<SceneBuffers, WreckageError> {
+    let geometry = Geometry::build(scene);
+
+    // Like the primitives, the buffer can't be empty
+    let mut materials = scene.materials.clone();
+    if materials.is_empty() {
+        materials.push(Material::default());
+    }
+
+    Ok(SceneBuffers {
+        primitives: storage_buffer(ctx, "primitives", geometry.primitives)?,
+        materials: storage_buffer(ctx, "materials", materials.iter().map(Material::raw))?,
+        bvh: storage_buffer(ctx, "bvh", geometry.bvh.raw())?,
+        lights: upload_lights(ctx, &scene.lights)?,
+        vertices: storage_buffer(ctx, "vertices", geometry.vertices)?,
+        indices: storage_buffer(ctx, "indices", geometry.indices)?,
+        mesh_bvh: storage_buffer(
+            ctx,
+            "mesh bvh",
+            geometry.mesh_nodes.iter().map(|node| node.raw()),
+        )?,
+    })
+}
+
+// A buffer the shaders read from, filled once from the CPU
+fn storage_buffer<T, I>(
+    ctx: &RenderingContext,
+    name: &str,
+    data: I,
+) -> Result<Subbuffer<[T]>, WreckageError>
+where
+    T: BufferContents,
+    I: IntoIterator<Item = T>,
+    I::IntoIter: ExactSizeIterator,
+{
+    let buffer = Buffer::from_iter(
+        &ctx.memory_allocator,
+        BufferCreateInfo {
+            usage: BufferUsage::STORAGE_BUFFER,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            usage: MemoryUsage::Upload,
+            ..Default::default()
+        },
+        data,
+    )?;
+    ctx.set_name(&**buffer.buffer(), name);
+    Ok(buffer)
+}
+
+/// Writes the lights into the buffer in place if their count didn't change, returns
+/// true if a new buffer was uploaded and the descriptors need to be recreated.
+/// The GPU must be done with the old lights.
+pub(crate) fn update_lights(
+    ctx: &RenderingContext,
+    buffers: &mut SceneBuffers,
+    lights: &[Light],
+) -> Result<bool, WreckageError> {
+    if lights.len().max(1) as u64 != buffers.lights.len() {
+        buffers.lights = upload_lights(ctx, lights)?;
+        return Ok(true);
+    }
+
+    let mut raw = buffers.lights.write()?;
+    for (i, slot) in raw.iter_mut().enumerate() {
+        *slot = lights.get(i).copied().unwrap_or_else(Light::none).raw();
+    }
+    Ok(false)
+}
+
+/// Uploads the lights on their own, so they can be changed without the rest of the scene
+pub(crate) fn upload_lights(
+    ctx: &RenderingContext,
+    lights: &[Light],
+) -> Result<Subbuffer<[RawLight]>, WreckageError> {
+    // Like the primitives, the buffer can't be empty, the padding light gives off nothing
+    let mut lights = lights.to_vec();
+    if lights.is_empty() {
+        lights.push(Light::none());
+    }
+
+    storage_buffer(ctx, "lights", lights.iter().map(Light::raw))
 }
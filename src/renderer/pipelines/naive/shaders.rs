@@ -2,6 +2,8 @@ use std::sync::Arc;
 
 use vulkano::{device::Device, shader::ShaderModule};
 
+use crate::WreckageError;
+
 mod cs {
     vulkano_shaders::shader! {
         ty: "compute",
@@ -9,6 +11,6 @@ mod cs {
     }
 }
 
-pub(crate) fn shader(device: Arc<Device>) -> Arc<ShaderModule> {
-    cs::load(device.clone()).expect("failed to create shader module")
+pub(crate) fn shader(device: Arc<Device>) -> Result<Arc<ShaderModule>, WreckageError> {
+    Ok(cs::load(device)?)
 }
@@ -0,0 +1,4 @@
+mod renderer;
+pub use renderer::*;
+mod shaders;
+use shaders::*;
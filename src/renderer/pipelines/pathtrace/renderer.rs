@@ -0,0 +1,650 @@
+use std::sync::Arc;
+
+use crate::{
+    naive::constants::RendererConstants,
+    naive::{
+        create_swapchain, update_lights, upload_scene, viewport_size, Frame, Resources,
+        DEFAULT_FRAMES_IN_FLIGHT, MAX_FRAMES_IN_FLIGHT,
+    },
+    Camera, Light, RawCamera, Scene,
+};
+use image::RgbaImage;
+use log::debug;
+use vulkano::{
+    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage},
+    command_buffer::{
+        AutoCommandBufferBuilder, BlitImageInfo, CommandBufferUsage, CopyImageToBufferInfo,
+        PrimaryAutoCommandBuffer,
+    },
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator,
+        layout::{
+            DescriptorSetLayout, DescriptorSetLayoutBinding, DescriptorSetLayoutCreateInfo,
+            DescriptorType,
+        },
+        PersistentDescriptorSet, WriteDescriptorSet,
+    },
+    device::Queue,
+    format::Format,
+    image::{view::ImageView, ImageAccess, ImageDimensions, StorageImage, SwapchainImage},
+    memory::allocator::{AllocationCreateInfo, MemoryUsage},
+    pipeline::{
+        layout::{PipelineLayoutCreateInfo, PushConstantRange},
+        ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+    },
+    shader::ShaderStages,
+    swapchain::{
+        self, AcquireError, Surface, Swapchain, SwapchainCreateInfo, SwapchainCreationError,
+        SwapchainPresentInfo,
+    },
+    sync::{self, FlushError, GpuFuture},
+};
+
+use crate::{Capabilities, RenderSettings, Renderer, RenderingContext, WreckageError};
+
+use super::shader;
+
+// Size of the workgroups declared in `pathtrace.comp`
+const WORKGROUP_SIZE: u32 = 4;
+// Samples per pixel a capture submits at once. Waiting between batches keeps long
+// captures from running into the driver's timeout.
+const CAPTURE_BATCH_SAMPLES: u32 = 64;
+
+#[derive(BufferContents)]
+#[repr(C)]
+struct PushConstants {
+    camera: RawCamera,
+    accumulated: u32,
+    samples: u32,
+    max_bounces: u32,
+}
+
+/// Traces paths bouncing through the scene, averaging the samples of every frame
+/// into an accumulation image until the camera or scene changes.
+pub struct PathTraceRenderer {
+    pub(crate) ctx: Arc<RenderingContext>,
+
+    pub(crate) surface_size: [u32; 2],
+    pub(crate) viewport_size: [u32; 2],
+    pub(crate) scale_factor: u32,
+
+    // Dataflow
+    pub(crate) queue: Arc<Queue>,
+    pub(crate) resources: Resources,
+    pub(crate) pipeline: Arc<ComputePipeline>,
+    pub(crate) frames: Vec<Frame>,
+    pub(crate) frame_index: usize,
+    pub(crate) previous_frame_end: Option<Box<dyn GpuFuture + Send + Sync>>,
+
+    // Sum of the samples so far, shared by every frame in flight so their traces take
+    // turns. Only submitted samples are counted.
+    pub(crate) accumulation: Arc<StorageImage>,
+    pub(crate) accumulated: u32,
+
+    // Presentation, missing when rendering headless
+    pub(crate) swapchain: Option<Arc<Swapchain>>,
+    pub(crate) swapchain_images: Vec<Arc<SwapchainImage>>,
+    pub(crate) recreate_swapchain: bool,
+
+    // Controls
+    pub(crate) camera: Camera,
+    pub(crate) settings: RenderSettings,
+}
+
+impl PathTraceRenderer {
+    /// Creates a renderer presenting to `surface`, tracing at half of its resolution
+    pub fn new(
+        ctx: Arc<RenderingContext>,
+        surface: Arc<Surface>,
+        scene: &Scene,
+    ) -> Result<Self, WreckageError> {
+        let (surface_size, swapchain, images) = create_swapchain(&ctx, surface)?;
+        Self::build(ctx, surface_size, 2, Some(swapchain), images, scene)
+    }
+
+    /// Creates a renderer without a window, frames can only be read back with `capture`
+    pub fn headless(
+        ctx: Arc<RenderingContext>,
+        size: [u32; 2],
+        scene: &Scene,
+    ) -> Result<Self, WreckageError> {
+        Self::build(ctx, size, 1, None, vec![], scene)
+    }
+
+    fn build(
+        ctx: Arc<RenderingContext>,
+        surface_size: [u32; 2],
+        scale_factor: u32,
+        swapchain: Option<Arc<Swapchain>>,
+        swapchain_images: Vec<Arc<SwapchainImage>>,
+        scene: &Scene,
+    ) -> Result<Self, WreckageError> {
+        // Queue to push the commands into
+        let queue = ctx
+            .queues
+            .first()
+            .ok_or(WreckageError::NoQueueFamily)?
+            .clone();
+
+        // Allocator for the descriptors
+        let descriptor_set_allocator = StandardDescriptorSetAllocator::new(ctx.device.clone());
+
+        // Layout of the descriptors in the set, the same as the naive pipeline
+        // with the accumulation image added at binding 4
+        let descriptor_set_layout = DescriptorSetLayout::new(
+            ctx.device.clone(),
+            DescriptorSetLayoutCreateInfo {
+                bindings: [
+                    (0, DescriptorType::StorageImage),
+                    (1, DescriptorType::StorageBuffer),
+                    (2, DescriptorType::StorageBuffer),
+                    (3, DescriptorType::StorageBuffer),
+                    (4, DescriptorType::StorageImage),
+                    (5, DescriptorType::StorageBuffer),
+                    (6, DescriptorType::StorageBuffer),
+                    (7, DescriptorType::StorageBuffer),
+                    (8, DescriptorType::StorageBuffer),
+                ]
+                .map(|(binding, ty)| {
+                    (
+                        binding,
+                        DescriptorSetLayoutBinding {
+                            stages: ShaderStages::COMPUTE,
+                            ..DescriptorSetLayoutBinding::descriptor_type(ty)
+                        },
+                    )
+                })
+                .into(),
+                ..Default::default()
+            },
+        )?;
+
+        // Push constants
+        let push_constants = vec![PushConstantRange {
+            stages: ShaderStages::COMPUTE,
+            size: std::mem::size_of::<PushConstants>() as u32,
+            ..Default::default()
+        }];
+
+        // The set of inputs that a pipeline processes
+        let pipeline_layout = PipelineLayout::new(
+            ctx.device.clone(),
+            PipelineLayoutCreateInfo {
+                set_layouts: vec![descriptor_set_layout.clone()],
+                push_constant_ranges: push_constants,
+                ..Default::default()
+            },
+        )?;
+
+        let resources = Resources {
+            descriptor_set_allocator,
+            descriptor_set_layout,
+            pipeline_layout,
+            shader: shader(ctx.device.clone())?,
+            scene: upload_scene(&ctx, scene)?,
+        };
+
+        let viewport_size = viewport_size(surface_size, scale_factor);
+        let pipeline = Self::create_pipeline(&ctx, &resources, viewport_size)?;
+        let accumulation = Self::create_accumulation(&ctx, &queue, viewport_size)?;
+        let frames = (0..DEFAULT_FRAMES_IN_FLIGHT)
+            .map(|_| Self::create_frame(&ctx, &queue, &resources, &accumulation, viewport_size))
+            .collect::<Result<_, _>>()?;
+        let previous_frame_end = Some(sync::now(ctx.device.clone()).boxed_send_sync());
+
+        Ok(Self {
+            camera: scene.camera.clone(),
+            settings: RenderSettings::default(),
+            ctx,
+            scale_factor,
+            viewport_size,
+            surface_size,
+            swapchain,
+            swapchain_images,
+            recreate_swapchain: false,
+            queue,
+            resources,
+            pipeline,
+            frames,
+            frame_index: 0,
+            previous_frame_end,
+            accumulation,
+            accumulated: 0,
+        })
+    }
+
+    /// Creates the pipeline, which bakes in the size of the frame
+    fn create_pipeline(
+        ctx: &RenderingContext,
+        resources: &Resources,
+        viewport_size: [u32; 2],
+    ) -> Result<Arc<ComputePipeline>, WreckageError> {
+        // The constants set up by the renderer
+        let consts = RendererConstants {
+            aspect_ratio: viewport_size[0] as f32 / viewport_size[1] as f32,
+            width: viewport_size[0],
+            height: viewport_size[1],
+        };
+
+        let pipeline = ComputePipeline::with_pipeline_layout(
+            ctx.device.clone(),
+            resources
+                .shader
+                .entry_point("main")
+                .expect("shaders are compiled with a main function"),
+            &consts,
+            resources.pipeline_layout.clone(),
+            None,
+        )?;
+        ctx.set_name(&*pipeline, "pathtrace pipeline");
+        Ok(pipeline)
+    }
+
+    /// Creates the image samples are summed into, its alpha holds the sample count
+    fn create_accumulation(
+        ctx: &RenderingContext,
+        queue: &Queue,
+        viewport_size: [u32; 2],
+    ) -> Result<Arc<StorageImage>, WreckageError> {
+        let accumulation = StorageImage::new(
+            &ctx.memory_allocator,
+            ImageDimensions::Dim2d {
+                width: viewport_size[0],
+                height: viewport_size[1],
+                array_layers: 1,
+            },
+            Format::R32G32B32A32_SFLOAT,
+            Some(queue.queue_family_index()),
+        )?;
+        ctx.set_name(&**accumulation.inner().image, "pathtrace accumulation");
+        Ok(accumulation)
+    }
+
+    /// Creates the image a frame is drawn onto and the descriptors pointing at it
+    fn create_frame(
+        ctx: &RenderingContext,
+        queue: &Queue,
+        resources: &Resources,
+        accumulation: &Arc<StorageImage>,
+        viewport_size: [u32; 2],
+    ) -> Result<Frame, WreckageError> {
+        // The buffer to draw onto
+        let out_image = StorageImage::new(
+            &ctx.memory_allocator,
+            ImageDimensions::Dim2d {
+                width: viewport_size[0],
+                height: viewport_size[1],
+                array_layers: 1,
+            },
+            Format::R8G8B8A8_UNORM,
+            Some(queue.queue_family_index()),
+        )?;
+        ctx.set_name(&**out_image.inner().image, "pathtrace frame");
+
+        // Descriptors to push into the pipeline
+        let descriptors = PersistentDescriptorSet::new(
+            &resources.descriptor_set_allocator,
+            resources.descriptor_set_layout.clone(),
+            [
+                WriteDescriptorSet::image_view(0, ImageView::new_default(out_image.clone())?),
+                WriteDescriptorSet::image_view(4, ImageView::new_default(accumulation.clone())?),
+            ]
+            .into_iter()
+            .chain(resources.scene.descriptor_writes()),
+        )?;
+
+        Ok(Frame {
+            out_image,
+            descriptors,
+            fence: None,
+        })
+    }
+
+    // Blocks until the GPU is done with every frame
+    fn wait_idle(&mut self) -> Result<(), WreckageError> {
+        for frame in &mut self.frames {
+            if let Some(fence) = frame.fence.take() {
+                fence.wait(None)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn recreate_target(&mut self) -> Result<(), WreckageError> {
+        self.wait_idle()?;
+        self.viewport_size = viewport_size(self.surface_size, self.scale_factor);
+        self.pipeline = Self::create_pipeline(&self.ctx, &self.resources, self.viewport_size)?;
+        self.accumulation = Self::create_accumulation(&self.ctx, &self.queue, self.viewport_size)?;
+        for frame in &mut self.frames {
+            *frame = Self::create_frame(
+                &self.ctx,
+                &self.queue,
+                &self.resources,
+                &self.accumulation,
+                self.viewport_size,
+            )?;
+        }
+        self.accumulated = 0;
+        Ok(())
+    }
+
+    // Returns false if the swapchain couldn't be recreated at the current size
+    fn recreate_swapchain(&mut self) -> Result<bool, WreckageError> {
+        let Some(swapchain) = &self.swapchain else {
+            return Ok(true);
+        };
+
+        let (swapchain, images) = match swapchain.recreate(SwapchainCreateInfo {
+            image_extent: self.surface_size,
+            ..swapchain.create_info()
+        }) {
+            Ok(r) => r,
+            // Happens while the window is being resized, try again next frame
+            Err(SwapchainCreationError::ImageExtentNotSupported { .. }) => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+
+        debug!(
+            "Recreated swapchain at {}x{}",
+            self.surface_size[0], self.surface_size[1]
+        );
+        self.swapchain = Some(swapchain);
+        self.swapchain_images = images;
+        self.recreate_swapchain = false;
+        self.recreate_target()?;
+        Ok(true)
+    }
+
+    // Samples to take in a pass after `accumulated` ones, zero once `max_samples` is reached
+    fn next_samples(&self, accumulated: u32) -> u32 {
+        match self.settings.max_samples {
+            0 => self.settings.samples_per_frame,
+            max => self
+                .settings
+                .samples_per_frame
+                .min(max.saturating_sub(accumulated)),
+        }
+    }
+
+    // Records a pass adding to `accumulated` samples, returns how many samples it takes
+    fn record_trace(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        descriptors: Arc<PersistentDescriptorSet>,
+        accumulated: u32,
+    ) -> Result<u32, WreckageError> {
+        let samples = self.next_samples(accumulated);
+        let [groups_x, groups_y] = self.viewport_size.map(|side| side.div_ceil(WORKGROUP_SIZE));
+        let push_constants = PushConstants {
+            camera: self.camera.raw(),
+            accumulated,
+            samples,
+            max_bounces: self.settings.max_depth,
+        };
+
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipeline.layout().clone(),
+                0u32,
+                descriptors,
+            )
+            .push_constants(self.pipeline.layout().clone(), 0, push_constants)
+            .dispatch([groups_x, groups_y, 1])?;
+
+        Ok(samples)
+    }
+}
+
+impl Renderer for PathTraceRenderer {
+    fn context(&self) -> Arc<RenderingContext> {
+        self.ctx.clone()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            name: "pathtrace",
+            presents: self.swapchain.is_some(),
+            max_frames_in_flight: MAX_FRAMES_IN_FLIGHT,
+            frames_in_flight: self.frames.len(),
+            progressive: true,
+            samples: self.accumulated,
+        }
+    }
+
+    fn draw(&mut self) -> Result<(), WreckageError> {
+        // Nothing to draw into while the window is minimised
+        if self.surface_size.contains(&0) {
+            return Ok(());
+        }
+
+        // Free whatever the GPU has finished with since the last frame
+        if let Some(previous_frame_end) = self.previous_frame_end.as_mut() {
+            previous_frame_end.cleanup_finished();
+        }
+
+        if self.recreate_swapchain && !self.recreate_swapchain()? {
+            return Ok(());
+        }
+
+        let swapchain = self.swapchain.clone().ok_or(WreckageError::Headless)?;
+
+        let (image_i, suboptimal, acquire_future) =
+            match swapchain::acquire_next_image(swapchain.clone(), None) {
+                Ok(r) => r,
+                Err(AcquireError::OutOfDate) => {
+                    self.recreate_swapchain = true;
+                    return Err(WreckageError::SwapchainOutOfDate);
+                }
+                Err(e) => return Err(e.into()),
+            };
+        if suboptimal {
+            self.recreate_swapchain = true;
+        }
+        let image = self.swapchain_images[image_i as usize].clone();
+
+        // The resources of this frame may only be reused once its last submission is done
+        let frame_i = self.frame_index;
+        if let Some(fence) = self.frames[frame_i].fence.take() {
+            fence.wait(None)?;
+        }
+        // The trace adds to what the previous frame wrote to the accumulation image
+        let previous_i = (frame_i + self.frames.len() - 1) % self.frames.len();
+        if let Some(fence) = &self.frames[previous_i].fence {
+            fence.wait(None)?;
+        }
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            &self.ctx.command_buffer_allocator,
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+
+        let descriptors = self.frames[frame_i].descriptors.clone();
+        let samples = self.record_trace(&mut builder, descriptors, self.accumulated)?;
+        builder.blit_image(BlitImageInfo::images(
+            self.frames[frame_i].out_image.clone(),
+            image,
+        ))?;
+
+        let command_buffer = builder.build()?;
+
+        let previous_frame_end = self
+            .previous_frame_end
+            .take()
+            .unwrap_or_else(|| sync::now(self.ctx.device.clone()).boxed_send_sync());
+
+        let future = previous_frame_end
+            .join(acquire_future)
+            .then_execute(self.queue.clone(), command_buffer)?
+            .then_swapchain_present(
+                self.queue.clone(),
+                SwapchainPresentInfo::swapchain_image_index(swapchain, image_i),
+            )
+            .then_signal_fence_and_flush();
+
+        match future {
+            Ok(future) => {
+                let fence = Arc::new(future);
+                self.frames[frame_i].fence = Some(fence.clone());
+                self.previous_frame_end = Some(fence.boxed_send_sync());
+                self.accumulated += samples;
+            }
+            Err(FlushError::OutOfDate) => {
+                self.recreate_swapchain = true;
+                self.previous_frame_end =
+                    Some(sync::now(self.ctx.device.clone()).boxed_send_sync());
+                self.frame_index = (frame_i + 1) % self.frames.len();
+                return Err(WreckageError::SwapchainOutOfDate);
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        self.frame_index = (frame_i + 1) % self.frames.len();
+        Ok(())
+    }
+
+    /// Accumulates up to `max_samples` samples, or a single frame if there's no limit
+    fn capture(&mut self) -> Result<RgbaImage, WreckageError> {
+        let [width, height] = self.viewport_size;
+        self.wait_idle()?;
+        let frame_i = self.frame_index;
+
+        // The buffer to read the frame back into
+        let readback = Buffer::new_slice::<u8>(
+            &self.ctx.memory_allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                usage: MemoryUsage::Download,
+                ..Default::default()
+            },
+            (width * height * 4) as u64,
+        )?;
+
+        // Every pass but the first adds to the samples of the previous one
+        let mut done = false;
+        while !done {
+            let mut builder = AutoCommandBufferBuilder::primary(
+                &self.ctx.command_buffer_allocator,
+                self.queue.queue_family_index(),
+                CommandBufferUsage::OneTimeSubmit,
+            )?;
+
+            let mut batched = 0;
+            loop {
+                let descriptors = self.frames[frame_i].descriptors.clone();
+                batched +=
+                    self.record_trace(&mut builder, descriptors, self.accumulated + batched)?;
+                done = self.next_samples(self.accumulated + batched) == 0
+                    || self.settings.max_samples == 0;
+                if done || batched >= CAPTURE_BATCH_SAMPLES {
+                    break;
+                }
+            }
+            if done {
+                builder.copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+                    self.frames[frame_i].out_image.clone(),
+                    readback.clone(),
+                ))?;
+            }
+
+            let command_buffer = builder.build()?;
+
+            sync::now(self.ctx.device.clone())
+                .then_execute(self.queue.clone(), command_buffer)?
+                .then_signal_fence_and_flush()?
+                .wait(None)?;
+            self.accumulated += batched;
+        }
+
+        let pixels = readback.read()?.to_vec();
+        Ok(RgbaImage::from_raw(width, height, pixels)
+            .expect("readback buffer is sized to the frame"))
+    }
+
+    fn resize(&mut self, surface_size: [u32; 2]) -> Result<(), WreckageError> {
+        if surface_size == self.surface_size {
+            return Ok(());
+        }
+
+        self.surface_size = surface_size;
+        if self.swapchain.is_some() {
+            self.recreate_swapchain = true;
+            Ok(())
+        } else {
+            self.recreate_target()
+        }
+    }
+
+    fn set_camera(&mut self, camera: &Camera) {
+        if *camera != self.camera {
+            self.camera = camera.clone();
+            self.accumulated = 0;
+        }
+    }
+
+    fn set_scene(&mut self, scene: &Scene) -> Result<(), WreckageError> {
+        self.wait_idle()?;
+        self.resources.scene = upload_scene(&self.ctx, scene)?;
+        // The descriptors of every frame point at the old buffers
+        self.recreate_target()
+    }
+
+    fn set_lights(&mut self, lights: &[Light]) -> Result<(), WreckageError> {
+        self.wait_idle()?;
+        if update_lights(&self.ctx, &mut self.resources.scene, lights)? {
+            self.recreate_target()?;
+        }
+        self.accumulated = 0;
+        Ok(())
+    }
+
+    fn set_frames_in_flight(&mut self, count: usize) -> Result<(), WreckageError> {
+        let count = count.clamp(1, MAX_FRAMES_IN_FLIGHT);
+        if count == self.frames.len() {
+            return Ok(());
+        }
+
+        self.wait_idle()?;
+        self.frames.truncate(count);
+        while self.frames.len() < count {
+            self.frames.push(Self::create_frame(
+                &self.ctx,
+                &self.queue,
+                &self.resources,
+                &self.accumulation,
+                self.viewport_size,
+            )?);
+        }
+        self.frame_index = 0;
+        Ok(())
+    }
+
+    fn set_settings(&mut self, settings: &RenderSettings) {
+        // More samples can be added to the current ones, other changes start over
+        if settings.max_depth != self.settings.max_depth {
+            self.accumulated = 0;
+        }
+        self.settings = RenderSettings {
+            samples_per_frame: settings.samples_per_frame.max(1),
+            ..*settings
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_constants_match_the_shader() {
+        assert_eq!(
+            std::mem::size_of::<PushConstants>(),
+            std::mem::size_of::<super::super::shaders::cs::PushConstants>()
+        );
+    }
+}
@@ -0,0 +1,16 @@
+use std::sync::Arc;
+
+use vulkano::{device::Device, shader::ShaderModule};
+
+use crate::WreckageError;
+
+pub(super) mod cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "./shaders/pathtrace.comp",
+    }
+}
+
+pub(crate) fn shader(device: Arc<Device>) -> Result<Arc<ShaderModule>, WreckageError> {
+    Ok(cs::load(device)?)
+}
@@ -0,0 +1,259 @@
+extern crate nalgebra_glm as glm;
+
+use std::{collections::HashMap, error::Error, fs, path::Path, sync::Arc};
+
+use glm::vec3;
+use serde::{Deserialize, Serialize};
+
+use crate::{Camera, Light, Material, Mesh, MeshInstance, Primitive, Sphere};
+
+/// Everything the renderer draws, along with the pose the camera starts at.
+/// Stored on disk as RON, see `scenes/example.ron`, or imported from glTF.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scene {
+    #[serde(default)]
+    pub camera: Camera,
+    #[serde(default)]
+    pub spheres: Vec<Sphere>,
+    /// Every other kind of shape, spheres may be listed here too
+    #[serde(default)]
+    pub primitives: Vec<Primitive>,
+    #[serde(default)]
+    pub meshes: Vec<MeshInstance>,
+    #[serde(default)]
+    pub materials: Vec<Material>,
+    #[serde(default)]
+    pub lights: Vec<Light>,
+}
+
+impl Scene {
+    /// Reads a RON scene, or a glTF one if the file ends in `.gltf` or `.glb`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let extension = path.as_ref().extension().and_then(|e| e.to_str());
+        if matches!(extension, Some("gltf" | "glb")) {
+            let mut scene = Self::empty();
+            scene.import_gltf(&path)?;
+            scene.validate()?;
+            return Ok(scene);
+        }
+
+        let source = fs::read_to_string(&path)?;
+        let mut scene = Self::parse(&source)?;
+        scene.load_meshes(path.as_ref().parent().unwrap_or(Path::new("")))?;
+        scene.validate()?;
+        Ok(scene)
+    }
+
+    /// Reads the OBJ files of the mesh instances, relative to `dir`. Instances of the
+    /// same file share one mesh.
+    pub fn load_meshes(&mut self, dir: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut loaded: HashMap<_, Arc<Mesh>> = HashMap::new();
+        for instance in &mut self.meshes {
+            if instance.path.as_os_str().is_empty() {
+                continue;
+            }
+
+            let path = dir.join(&instance.path);
+            instance.mesh = match loaded.get(&path) {
+                Some(mesh) => mesh.clone(),
+                None => {
+                    let mesh = Mesh::load_obj(&path)
+                        .map_err(|e| format!("failed to load {}: {e}", path.display()))?;
+                    let mesh = Arc::new(mesh);
+                    loaded.insert(path, mesh.clone());
+                    mesh
+                }
+            };
+        }
+        Ok(())
+    }
+
+    /// Reads a scene from RON. Primitives can be written as `Plane(normal: ..)`
+    /// rather than `Plane((normal: ..))`.
+    pub fn parse(source: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let options = ron::Options::default()
+            .with_default_extension(ron::extensions::Extensions::UNWRAP_VARIANT_NEWTYPES);
+        Ok(options.from_str(source)?)
+    }
+
+    /// The spheres followed by the other primitives, in the order they are numbered in errors
+    pub fn all_primitives(&self) -> impl Iterator<Item = Primitive> + '_ {
+        self.spheres
+            .iter()
+            .cloned()
+            .map(Primitive::Sphere)
+            .chain(self.primitives.iter().cloned())
+    }
+
+    /// Checks that every primitive refers to a material that exists
+    pub fn validate(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        for (i, primitive) in self.all_primitives().enumerate() {
+            if let Some(material) = primitive.material() {
+                if material as usize >= self.materials.len() {
+                    return Err(format!(
+                        "primitive {i} uses material {material}, but only {} are defined",
+                        self.materials.len()
+                    )
+                    .into());
+                }
+            }
+        }
+
+        for (i, instance) in self.meshes.iter().enumerate() {
+            if let Some(material) = instance.material {
+                if material as usize >= self.materials.len() {
+                    return Err(format!(
+                        "mesh {i} uses material {material}, but only {} are defined",
+                        self.materials.len()
+                    )
+                    .into());
+                }
+            }
+            if instance.mesh.triangle_count() == 0 {
+                return Err(format!("mesh {i} has no triangles").into());
+            }
+            instance
+                .mesh
+                .validate()
+                .map_err(|e| format!("mesh {i} is broken, {e}"))?;
+            // The shader divides by it to bring rays into the mesh's space
+            if instance.scale.is_nan() || instance.scale <= 0.0 {
+                return Err(format!("mesh {i} has a scale of {}", instance.scale).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// A scene with nothing in it, seen from the origin
+    pub fn empty() -> Self {
+        Self {
+            camera: Camera::default(),
+            spheres: vec![],
+            primitives: vec![],
+            meshes: vec![],
+            materials: vec![],
+            lights: vec![],
+        }
+    }
+
+    /// The 16x16x16 grid of spheres used when no scene file is given
+    pub fn grid() -> Self {
+        let mut spheres = vec![];
+        for x in 0..16 {
+            for y in 0..16 {
+                for z in 0..16 {
+                    spheres.push(Sphere::new(
+                        vec3(x as f32, y as f32, z as f32),
+                        ((x * y + z) % 5 + 1) as f32 / 20.0,
+                    ));
+                }
+            }
+        }
+
+        Self {
+            camera: Camera {
+                position: vec3(1.0, 0.0, 0.0),
+                ..Default::default()
+            },
+            spheres,
+            primitives: vec![],
+            meshes: vec![],
+            materials: vec![],
+            lights: vec![],
+        }
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::grid()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Plane, Vertex};
+    use glm::Vec3;
+
+    #[test]
+    fn materials_parse_from_ron() {
+        let scene: Scene = ron::from_str(
+            "(
+                spheres: [(pos: [0.0, 0.0, 0.0], radius: 1.0, material: Some(1))],
+                materials: [
+                    Dielectric(ior: 1.5),
+                    Metal(albedo: [1.0, 0.5, 0.0], roughness: 0.3),
+                ],
+            )",
+        )
+        .unwrap();
+
+        assert!(scene.validate().is_ok());
+        assert_eq!(
+            scene.materials[1],
+            Material::Metal {
+                albedo: vec3(1.0, 0.5, 0.0),
+                roughness: 0.3
+            }
+        );
+    }
+
+    #[test]
+    fn missing_material_is_rejected() {
+        let scene = Scene {
+            camera: Camera::default(),
+            spheres: vec![Sphere::new(vec3(0.0, 0.0, 0.0), 1.0).with_material(0)],
+            primitives: vec![],
+            meshes: vec![],
+            materials: vec![],
+            lights: vec![],
+        };
+
+        assert!(scene.validate().is_err());
+    }
+
+    #[test]
+    fn broken_meshes_are_rejected() {
+        let vertex = Vertex::default();
+        assert!(Mesh::new(vec![vertex; 3], vec![0, 1, 3]).is_err());
+
+        let mesh = Arc::new(Mesh::new(vec![vertex; 3], vec![0, 1, 2]).unwrap());
+        let mut scene = Scene::empty();
+        scene.meshes = vec![MeshInstance::new(mesh.clone())];
+        assert!(scene.validate().is_ok());
+
+        scene.meshes =
+            vec![MeshInstance::new(mesh).with_transform(Vec3::zeros(), Vec3::zeros(), 0.0)];
+        assert!(scene.validate().is_err());
+
+        let broken = Mesh {
+            vertices: vec![vertex; 3],
+            indices: vec![0, 1, 5],
+        };
+        scene.meshes = vec![MeshInstance::new(Arc::new(broken))];
+        assert!(scene.validate().is_err());
+    }
+
+    #[test]
+    fn primitives_parse_without_extra_parentheses() {
+        let scene = Scene::parse(
+            "(
+                primitives: [
+                    Plane(normal: [0.0, 1.0, 0.0], distance: -1.0, material: Some(0)),
+                    Torus(center: [0.0, 1.0, 0.0], major_radius: 1.0, minor_radius: 0.2),
+                ],
+                materials: [Diffuse(albedo: [0.5, 0.5, 0.5])],
+            )",
+        )
+        .unwrap();
+
+        assert!(scene.spheres.is_empty());
+        assert!(scene.validate().is_ok());
+        assert_eq!(
+            scene.primitives[0],
+            Primitive::Plane(Plane::new(vec3(0.0, 1.0, 0.0), -1.0).with_material(0))
+        );
+        assert_eq!(scene.all_primitives().count(), 2);
+    }
+}
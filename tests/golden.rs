@@ -0,0 +1,202 @@
+//! Renders fixed scenes through the headless mode and compares them against
+//! the reference images in `tests/golden/reference`.
+//!
+//! The frames are rendered by the naive pipeline through Vulkan, never on the
+//! CPU, so the tests are ignored by default and fail without a device. Where
+//! there's one, lavapipe (Mesa's software driver) will do, they run with
+//!
+//! ```text
+//! cargo test --test golden -- --ignored
+//! ```
+//!
+//! The references are made the same way at 160x120 with `WRECKAGE_BLESS=1` set,
+//! which overwrites them with the current output after an intended change to the
+//! image. The ones checked in so far were rendered by `wreckage --headless --cpu`,
+//! the `ReferenceRenderer` port of the naive shader, and are to be blessed again
+//! on a device. When a comparison fails, the frame and a diff image are written
+//! next to the test binaries.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use image::{Rgba, RgbaImage};
+
+const WIDTH: u32 = 160;
+const HEIGHT: u32 = 120;
+
+// Exit code of `wreckage --gpu-only` when it finds no Vulkan device
+const NO_GPU_EXIT_CODE: i32 = 3;
+
+// Channel difference a pixel may have before it counts as different
+const PIXEL_TOLERANCE: u8 = 8;
+// Share of the pixels allowed to exceed the tolerance
+const MAX_DIFFERENT_PIXELS: f64 = 0.005;
+// Lowest mean structural similarity of the luma accepted as a match
+const MIN_SSIM: f64 = 0.98;
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn render(name: &str, scene: Option<&str>) -> RgbaImage {
+    let output = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{name}.png"));
+
+    let mut command = Command::new(env!("CARGO_BIN_EXE_wreckage"));
+    if let Some(scene) = scene {
+        command.arg(golden_dir().join("scenes").join(scene));
+    }
+    let status = command
+        .arg("--headless")
+        .arg("--gpu-only")
+        .arg("--output")
+        .arg(&output)
+        .args([
+            "--width",
+            &WIDTH.to_string(),
+            "--height",
+            &HEIGHT.to_string(),
+        ])
+        .status()
+        .expect("failed to run wreckage");
+    assert_ne!(
+        status.code(),
+        Some(NO_GPU_EXIT_CODE),
+        "no Vulkan device to render {name} on, lavapipe works"
+    );
+    assert!(status.success(), "rendering {name} failed with {status}");
+
+    image::open(&output)
+        .expect("failed to read the rendered frame")
+        .to_rgba8()
+}
+
+fn luma(pixel: &Rgba<u8>) -> f64 {
+    0.299 * pixel[0] as f64 + 0.587 * pixel[1] as f64 + 0.114 * pixel[2] as f64
+}
+
+/// Mean SSIM of the luma over 8x8 windows
+fn ssim(a: &RgbaImage, b: &RgbaImage) -> f64 {
+    const WINDOW: u32 = 8;
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    let mut total = 0.0;
+    let mut windows = 0;
+    for wy in (0..a.height() - WINDOW + 1).step_by(WINDOW as usize) {
+        for wx in (0..a.width() - WINDOW + 1).step_by(WINDOW as usize) {
+            let samples = (0..WINDOW * WINDOW)
+                .map(|i| (wx + i % WINDOW, wy + i / WINDOW))
+                .map(|(x, y)| (luma(a.get_pixel(x, y)), luma(b.get_pixel(x, y))))
+                .collect::<Vec<_>>();
+            let n = samples.len() as f64;
+
+            let mean_a = samples.iter().map(|s| s.0).sum::<f64>() / n;
+            let mean_b = samples.iter().map(|s| s.1).sum::<f64>() / n;
+            let (mut var_a, mut var_b, mut cov) = (0.0, 0.0, 0.0);
+            for (la, lb) in &samples {
+                var_a += (la - mean_a) * (la - mean_a) / n;
+                var_b += (lb - mean_b) * (lb - mean_b) / n;
+                cov += (la - mean_a) * (lb - mean_b) / n;
+            }
+
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * cov + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+
+    total / windows as f64
+}
+
+fn check_golden(name: &str, scene: Option<&str>) {
+    let frame = render(name, scene);
+    let reference_path = golden_dir().join("reference").join(format!("{name}.png"));
+
+    if std::env::var_os("WRECKAGE_BLESS").is_some() {
+        frame.save(&reference_path).unwrap();
+        return;
+    }
+
+    let reference = image::open(&reference_path)
+        .unwrap_or_else(|e| panic!("missing reference {}: {e}", reference_path.display()))
+        .to_rgba8();
+    assert_eq!(frame.dimensions(), reference.dimensions());
+
+    // Differences are scaled up so small errors are still visible
+    let mut different = 0;
+    let diff = RgbaImage::from_fn(WIDTH, HEIGHT, |x, y| {
+        let (a, b) = (frame.get_pixel(x, y), reference.get_pixel(x, y));
+        let channel = |i: usize| a[i].abs_diff(b[i]);
+        if (0..3).any(|i| channel(i) > PIXEL_TOLERANCE) {
+            different += 1;
+        }
+        Rgba([
+            channel(0).saturating_mul(8),
+            channel(1).saturating_mul(8),
+            channel(2).saturating_mul(8),
+            u8::MAX,
+        ])
+    });
+
+    let different_share = different as f64 / (WIDTH * HEIGHT) as f64;
+    let similarity = ssim(&frame, &reference);
+    if different_share > MAX_DIFFERENT_PIXELS || similarity < MIN_SSIM {
+        let diff_path = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{name}.diff.png"));
+        diff.save(&diff_path).unwrap();
+        panic!(
+            "{name} differs from its reference: {:.2}% of pixels off, SSIM {similarity:.4}, diff written to {}",
+            different_share * 100.0,
+            diff_path.display()
+        );
+    }
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn default_grid() {
+    check_golden("default_grid", None);
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn spheres_front() {
+    check_golden("spheres_front", Some("spheres_front.ron"));
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn spheres_tilted() {
+    check_golden("spheres_tilted", Some("spheres_tilted.ron"));
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn overlapping() {
+    check_golden("overlapping", Some("overlapping.ron"));
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn lights() {
+    check_golden("lights", Some("lights.ron"));
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn primitives() {
+    check_golden("primitives", Some("primitives.ron"));
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn meshes() {
+    check_golden("meshes", Some("meshes.ron"));
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn gltf() {
+    check_golden("gltf", Some("gltf.gltf"));
+}